@@ -1,23 +1,22 @@
 use axum::{Router, routing::get};
 use axum_proxied::extract;
 
-async fn handler(
-    forwarded: Option<extract::Forwarded>,
-    xforwarded: Option<extract::XForwardedFor>,
-) -> String {
-    if let Some(ref forwarded) = forwarded {
-        println!("forwarded: first: {:?}", forwarded.forwards().first());
-        for f in forwarded.forwards() {
-            println!("forwarded: for {:?}", f.r#for());
+async fn handler(info: Option<extract::ForwardedInfo>) -> String {
+    if let Some(ref info) = info {
+        println!("hops from {:?}", info.source());
+        println!("first: {:?}", info.hops().first());
+        for hop in info.hops() {
+            println!(
+                "for {:?} by {:?}, {:?}://{:?}:{:?}",
+                hop.r#for(),
+                hop.by(),
+                hop.proto(),
+                hop.host(),
+                hop.port()
+            );
         }
     }
-    if let Some(ref xforwarded) = xforwarded {
-        println!("xforwarded: first: {:?}", xforwarded.forwards().first());
-        for f in xforwarded.forwards() {
-            println!("xforwarded: for {:?}", f);
-        }
-    }
-    format!("oy, {forwarded:?}, or potentially {xforwarded:?}\n")
+    format!("oy, {info:?}\n")
 }
 
 #[tokio::main]
@@ -0,0 +1,29 @@
+#![no_main]
+
+use axum_proxied::Strictness;
+use axum_proxied::extract::forwarded::{Forward, Forwarded};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = std::str::from_utf8(data) else {
+        return ();
+    };
+
+    // Whatever we write back out has to parse to the same thing.
+    if let Ok(strict) = Forwarded::parse(value, Strictness::Strict) {
+        let written = strict.to_string();
+        assert_eq!(Forwarded::parse(&written, Strictness::Strict), Ok(strict));
+    }
+
+    // Lenient parsing keeps an empty forward for each element with nothing usable in it, and
+    // those don't get written.
+    let Ok(lenient) = value.parse::<Forwarded>();
+    let forwards: Vec<Forward> = lenient
+        .forwards()
+        .iter()
+        .filter(|forward| **forward != Forward::default())
+        .cloned()
+        .collect();
+    let written = lenient.to_string().parse::<Forwarded>();
+    assert_eq!(written.map(|w| w.forwards().clone()), Ok(forwards));
+});
@@ -1,11 +1,86 @@
-//! Extracts the [Forwarded][docs-forwarded] and
-//! [X-Forwarded-For][docs-forwarded-for] header.
+//! Extracts the [Forwarded][docs-forwarded] header and the `X-Forwarded-*` family, e.g.
+//! [X-Forwarded-For][docs-forwarded-for], and the client's IP from them.
 //!
 //! [docs-forwarded]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Forwarded
 //! [docs-forwarded-for]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/X-Forwarded-For
 
+pub mod allowedhost;
+pub mod clientip;
+pub mod externalurl;
 pub mod forwarded;
+pub mod forwardedinfo;
+pub mod ipheader;
+pub mod rejection;
 pub mod xforwardedfor;
+pub mod xforwardedhost;
+pub mod xforwardedport;
+pub mod xforwardedprefix;
+pub mod xforwardedproto;
 
+pub use crate::extract::allowedhost::*;
+pub use crate::extract::clientip::*;
+pub use crate::extract::externalurl::*;
 pub use crate::extract::forwarded::*;
+pub use crate::extract::forwardedinfo::*;
+pub use crate::extract::ipheader::*;
+pub use crate::extract::rejection::*;
 pub use crate::extract::xforwardedfor::*;
+pub use crate::extract::xforwardedhost::*;
+pub use crate::extract::xforwardedport::*;
+pub use crate::extract::xforwardedprefix::*;
+pub use crate::extract::xforwardedproto::*;
+
+use crate::Strictness;
+use axum::http::header::{AsHeaderName, HeaderMap, ToStrError};
+use std::borrow::Cow;
+
+/// Every line of a comma-separated list header, joined in order, as if it had been sent as one
+/// line. `None` if there aren't any.
+pub(crate) fn list_header(
+    headers: &HeaderMap,
+    name: impl AsHeaderName,
+) -> Result<Option<Cow<'_, str>>, ToStrError> {
+    let mut lines = headers.get_all(name).iter();
+    let Some(first) = lines.next() else {
+        return Ok(None);
+    };
+    let mut joined = Cow::Borrowed(first.to_str()?);
+    for line in lines {
+        let joined = joined.to_mut();
+        joined.push_str(", ");
+        joined.push_str(line.to_str()?);
+    }
+    Ok(Some(joined))
+}
+
+/// Every line of a header, in order. `None` if there aren't any.
+pub(crate) fn header_lines(
+    headers: &HeaderMap,
+    name: impl AsHeaderName,
+) -> Result<Option<Vec<&str>>, ToStrError> {
+    let lines = headers
+        .get_all(name)
+        .iter()
+        .map(|line| line.to_str())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(lines).filter(|lines| !lines.is_empty()))
+}
+
+/// The entries of a comma-separated list, trimmed, and without the empty ones. `parse` gives an
+/// `Err` for an entry which is invalid, with what to keep in its place when lenient. When
+/// strict, the first invalid entry is the error, with its index.
+pub(crate) fn parse_list<T>(
+    s: &str,
+    strictness: Strictness,
+    parse: impl Fn(&str) -> Result<T, T>,
+) -> Result<Vec<T>, (usize, &str)> {
+    let entries = s.split(',').map(str::trim).filter(|e| !e.is_empty());
+    let mut parsed = vec![];
+    for (index, entry) in entries.enumerate() {
+        match (parse(entry), strictness) {
+            (Ok(value), _) | (Err(value), Strictness::Lenient) => parsed.push(value),
+            (Err(_), Strictness::Strict) => return Err((index, entry)),
+        }
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,575 @@
+//! Works out which host the client asked for, and checks it's one we serve.
+//!
+//! Anything built from the host, e.g. a password reset link, is only as trustworthy as where
+//! the host came from: `Host`, `Forwarded: host=` and `X-Forwarded-Host` are all whatever the
+//! client wants them to be, unless a proxy we trust set them. So we only believe forwarded hosts
+//! from hops added by [`TrustedProxies`], and whatever we end up with has to be in the
+//! [`AllowedHosts`].
+use crate::extract::clientip::{HopFor, HopHeader, TrustedProxies, peer_ip};
+use crate::extract::forwardedinfo::{self, ForwardedInfoRejection, Hop};
+use crate::extract::rejection::{Rejection, RejectionKind, impl_into_response_via_rejection};
+use axum::body::Body;
+use axum::extract::FromRequestParts;
+use axum::http::header::{self, HeaderName};
+use axum::http::request::Parts;
+use axum::http::uri::Authority;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+use std::str::FromStr;
+
+/// Split `value` into a lowercase host (IPv6 in brackets, without a trailing dot) and the port,
+/// if it's an authority without userinfo.
+fn split_authority(value: &str) -> Option<(String, Option<u16>)> {
+    if value.contains('@') {
+        return None;
+    }
+    let authority = value.parse::<Authority>().ok()?;
+    let host = authority.host().to_ascii_lowercase();
+    let host = host.strip_suffix('.').map(String::from).unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, authority.port_u16()))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct HostPattern {
+    /// `host` is a suffix, which only matches subdomains.
+    wildcard: bool,
+    host: String,
+    port: Option<u16>,
+}
+
+impl HostPattern {
+    fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        if self.port.is_some() && self.port != port {
+            return false;
+        }
+        if self.wildcard {
+            host.strip_suffix(&self.host)
+                .and_then(|subdomain| subdomain.strip_suffix('.'))
+                .is_some_and(|subdomain| !subdomain.is_empty())
+        } else {
+            host == self.host
+        }
+    }
+}
+
+/// A host pattern that doesn't parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidHostPattern(String);
+
+impl InvalidHostPattern {
+    /// The pattern as given.
+    pub fn pattern(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InvalidHostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid host pattern {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidHostPattern {}
+
+/// The hosts we serve, for [`AllowedHost`].
+///
+/// Each pattern is a host, e.g. `example.com` or `[2001:db8::1]`, or a wildcard for any
+/// subdomain (but not the domain itself), e.g. `*.example.com`. Either can have a port, e.g.
+/// `example.com:8443`, in which case only that port is allowed; otherwise any port is. Hosts are
+/// compared case-insensitively, ignoring a trailing dot.
+///
+/// Put it in the request's extensions, e.g. with `.layer(axum::Extension(allowed))`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllowedHosts {
+    patterns: Vec<HostPattern>,
+}
+
+impl AllowedHosts {
+    /// A new one...
+    pub fn new<I>(patterns: I) -> Result<Self, InvalidHostPattern>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut allowed = Self::default();
+        for pattern in patterns {
+            allowed.insert(pattern.as_ref())?;
+        }
+        Ok(allowed)
+    }
+
+    /// Allow one more pattern.
+    pub fn insert(&mut self, pattern: &str) -> Result<(), InvalidHostPattern> {
+        let invalid = || InvalidHostPattern(String::from(pattern));
+        let (wildcard, rest) = match pattern.trim().strip_prefix("*.") {
+            Some(rest) => (true, rest),
+            None => (false, pattern.trim()),
+        };
+        let (host, port) = split_authority(rest).ok_or_else(invalid)?;
+        if host.contains('*') || (wildcard && host.starts_with('[')) {
+            return Err(invalid());
+        }
+        self.patterns.push(HostPattern {
+            wildcard,
+            host,
+            port,
+        });
+        Ok(())
+    }
+
+    /// Whether `host` (as from [`AllowedHost::host`]) and `port` match any of the patterns.
+    pub fn allows(&self, host: &str, port: Option<u16>) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches(host, port))
+    }
+}
+
+/// Patterns separated by commas and/or whitespace.
+impl FromStr for AllowedHosts {
+    type Err = InvalidHostPattern;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(
+            s.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|pattern| !pattern.is_empty()),
+        )
+    }
+}
+
+/// The host the client asked for, checked against the [`AllowedHosts`] in the request's
+/// extensions.
+///
+/// If there are [`TrustedProxies`] in the request's extensions, and the peer is one of them,
+/// the host is from the client's closest trusted hop that says, in whichever header the
+/// proxies use (`Forwarded` or `X-Forwarded-Host`). Otherwise, and if none of them say, it's
+/// the `Host` header, or the URI's authority for HTTP/2.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Extension, Router, routing::get};
+/// use axum_proxied::extract::{AllowedHost, AllowedHosts, TrustedProxies};
+///
+/// async fn handler(host: AllowedHost) -> String {
+///     format!("https://{host}/reset?token=...")
+/// }
+///
+/// let allowed: AllowedHosts = "example.com, *.example.com".parse().unwrap();
+/// let trusted = TrustedProxies::networks("10.0.0.0/8".parse().unwrap());
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(allowed))
+///     .layer(Extension(trusted));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllowedHost {
+    host: String,
+    port: Option<u16>,
+}
+
+impl AllowedHost {
+    /// A new one...
+    pub fn new(host: String, port: Option<u16>) -> Self {
+        Self { host, port }
+    }
+
+    /// The host, lowercase, with IPv6 addresses in brackets.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port, if there was one.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// `host[:port]`
+impl fmt::Display for AllowedHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{port}", self.host),
+            None => f.write_str(&self.host),
+        }
+    }
+}
+
+/// Why we couldn't get an allowed host.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AllowedHostRejection {
+    /// There wasn't a host anywhere.
+    Missing,
+    /// The `Host` header's gotta be at least UTF-8.
+    NotAString,
+    /// The `Host` header was sent more than once, so we can't tell which to believe.
+    Duplicate,
+    /// The host from this header isn't a `host[:port]`.
+    Invalid(HeaderName),
+    /// The host from this header isn't one of the [`AllowedHosts`].
+    NotAllowed(HeaderName, String),
+    /// [`AllowedHost`] or [`ExternalUrl`](crate::extract::ExternalUrl) was used without
+    /// [`AllowedHosts`] in the request's extensions.
+    MissingAllowedHosts,
+    /// There are [`TrustedProxies`], but we weren't told who connected to us; the service needs
+    /// connect info.
+    MissingConnectInfo,
+    /// We couldn't parse the hops from our trusted proxies.
+    Hops(ForwardedInfoRejection),
+    /// A proxy we trust sent this hop (counting from `0`) in this header, with a `for` we
+    /// couldn't make sense of, so we can't tell which hops are theirs.
+    InvalidHop(HopHeader, usize),
+}
+
+impl fmt::Display for AllowedHostRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing Host header"),
+            Self::NotAString => f.write_str("could not parse Host header into string"),
+            Self::Duplicate => f.write_str("Host header was sent more than once"),
+            Self::Invalid(name) => write!(f, "could not parse host in {name} header"),
+            Self::NotAllowed(name, host) => {
+                write!(f, "host {host:?} in {name} header is not allowed")
+            }
+            Self::MissingAllowedHosts => f.write_str("missing AllowedHosts extension"),
+            Self::MissingConnectInfo => {
+                f.write_str("missing connect info, could not determine trusted host")
+            }
+            Self::Hops(rejection) => rejection.fmt(f),
+            Self::InvalidHop(source, index) => write!(
+                f,
+                "{} hop {index} from a trusted proxy has an invalid `for`",
+                source.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AllowedHostRejection {}
+
+impl From<AllowedHostRejection> for Rejection {
+    fn from(value: AllowedHostRejection) -> Self {
+        let message = value.to_string();
+        let (kind, header) = match value {
+            AllowedHostRejection::Missing => (RejectionKind::Missing, Some(header::HOST)),
+            AllowedHostRejection::NotAString => (RejectionKind::NotAString, Some(header::HOST)),
+            AllowedHostRejection::Duplicate => (RejectionKind::Duplicate, Some(header::HOST)),
+            AllowedHostRejection::Invalid(name) => (RejectionKind::Invalid, Some(name)),
+            AllowedHostRejection::NotAllowed(name, _) => (RejectionKind::NotAllowed, Some(name)),
+            AllowedHostRejection::MissingAllowedHosts
+            | AllowedHostRejection::MissingConnectInfo => (RejectionKind::Misconfigured, None),
+            AllowedHostRejection::Hops(rejection) => return Rejection::from(rejection),
+            AllowedHostRejection::InvalidHop(source, _) => {
+                (RejectionKind::Invalid, Some(source.name()))
+            }
+        };
+        Rejection::new(kind, header, message)
+    }
+}
+
+impl_into_response_via_rejection!(AllowedHostRejection);
+
+const X_FORWARDED_HOST_HEADER: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// The hops added by proxies we trust, with the client's closest first, and which header they
+/// came from. There aren't any without [`TrustedProxies`], or if the peer isn't one of them.
+pub(crate) async fn trusted_hops<S>(
+    parts: &mut Parts,
+    state: &S,
+) -> Result<(Vec<Hop>, HopHeader), AllowedHostRejection>
+where
+    S: Send + Sync,
+{
+    let Some(trusted) = parts.extensions.get::<TrustedProxies>().cloned() else {
+        return Ok((vec![], HopHeader::default()));
+    };
+    let source = trusted.hop_header();
+    let Some(peer) = peer_ip(parts) else {
+        return Err(AllowedHostRejection::MissingConnectInfo);
+    };
+    if !trusted.is_trusted(peer, 0) {
+        // Don't bother with the headers, they're whatever the client wants them to be.
+        return Ok((vec![], source));
+    }
+    let mut hops = forwardedinfo::hops(parts, state, source)
+        .await
+        .map_err(AllowedHostRejection::Hops)?
+        .unwrap_or_default();
+    let fors: Vec<_> = hops
+        .iter()
+        .map(|hop| HopFor::of(hop.r#for().as_ref()))
+        .collect();
+    let (_, count) = trusted.walk(peer, &fors);
+    // A missing `for` is fine, not every X-Forwarded-* list has to be as long as the others.
+    let leftmost = hops.len() - count;
+    if let Some(HopFor::Invalid) = fors.get(leftmost).filter(|_| count > 0) {
+        return Err(AllowedHostRejection::InvalidHop(source, leftmost));
+    }
+    hops.drain(..hops.len() - count);
+    Ok((hops, source))
+}
+
+/// The host from the closest of the trusted `hops` that says, otherwise from the `Host` header
+/// or the URI, checked against `allowed` if there is one.
+pub(crate) fn resolve(
+    parts: &Parts,
+    hops: &[Hop],
+    source: HopHeader,
+    allowed: &AllowedHosts,
+) -> Result<AllowedHost, AllowedHostRejection> {
+    let forwarded = hops
+        .iter()
+        .find_map(|hop| hop.host().as_ref().map(|host| (host.clone(), hop.port())));
+    let (name, value, fallback_port) = match forwarded {
+        Some((value, port)) => {
+            let name = match source {
+                HopHeader::Forwarded => header::FORWARDED,
+                HopHeader::XForwardedFor => X_FORWARDED_HOST_HEADER,
+            };
+            (name, value, port)
+        }
+        None => {
+            let mut values = parts.headers.get_all(header::HOST).iter();
+            let value = match (values.next(), values.next()) {
+                (Some(_), Some(_)) => return Err(AllowedHostRejection::Duplicate),
+                (Some(value), None) => value
+                    .to_str()
+                    .map_err(|_| AllowedHostRejection::NotAString)?
+                    .to_owned(),
+                (None, _) => match parts.uri.authority() {
+                    Some(authority) => authority.to_string(),
+                    None => return Err(AllowedHostRejection::Missing),
+                },
+            };
+            (header::HOST, value, None)
+        }
+    };
+    let Some((host, port)) = split_authority(value.trim()) else {
+        return Err(AllowedHostRejection::Invalid(name));
+    };
+    let port = port.or(fallback_port);
+    if !allowed.allows(&host, port) {
+        return Err(AllowedHostRejection::NotAllowed(name, value));
+    }
+    Ok(AllowedHost::new(host, port))
+}
+
+impl<S> FromRequestParts<S> for AllowedHost
+where
+    S: Send + Sync,
+{
+    type Rejection = AllowedHostRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(allowed) = parts.extensions.get::<AllowedHosts>().cloned() else {
+            return Err(AllowedHostRejection::MissingAllowedHosts);
+        };
+        let (hops, source) = trusted_hops(parts, state).await?;
+        resolve(parts, &hops, source, &allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    async fn allowed_host(
+        trusted: Option<TrustedProxies>,
+        headers: &[(&str, &str)],
+    ) -> Result<AllowedHost, AllowedHostRejection> {
+        let mut builder = axum::http::request::Builder::new()
+            .method("GET")
+            .extension(ConnectInfo(
+                "10.0.0.1:1234".parse::<SocketAddr>().expect("???"),
+            ))
+            .extension(
+                "example.com, *.example.org, localhost:8080"
+                    .parse::<AllowedHosts>()
+                    .expect("could not parse hosts"),
+            );
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        if let Some(trusted) = trusted {
+            builder = builder.extension(trusted);
+        }
+        let (mut parts, _) = builder
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        AllowedHost::from_request_parts(&mut parts, &()).await
+    }
+
+    fn host(host: &str, port: Option<u16>) -> AllowedHost {
+        AllowedHost::new(String::from(host), port)
+    }
+
+    #[test]
+    fn patterns() {
+        let allowed: AllowedHosts = "Example.COM. *.example.org [2001:db8::1]:443"
+            .parse()
+            .expect("could not parse hosts");
+        assert!(allowed.allows("example.com", None));
+        assert!(allowed.allows("example.com", Some(8443)));
+        assert!(!allowed.allows("www.example.com", None));
+        assert!(allowed.allows("a.b.example.org", None));
+        assert!(!allowed.allows("example.org", None));
+        assert!(!allowed.allows("evilexample.org", None));
+        assert!(allowed.allows("[2001:db8::1]", Some(443)));
+        assert!(!allowed.allows("[2001:db8::1]", None));
+        for pattern in [
+            "",
+            "*",
+            "*.",
+            "user@example.com",
+            "*.[2001:db8::1]",
+            "a.*.com",
+        ] {
+            assert_eq!(
+                AllowedHosts::new([pattern]),
+                Err(InvalidHostPattern(String::from(pattern))),
+                "{pattern}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn host_header() {
+        assert_eq!(
+            allowed_host(None, &[("Host", "WWW.Example.org:8443")]).await,
+            Ok(host("www.example.org", Some(8443)))
+        );
+        assert_eq!(
+            allowed_host(None, &[("Host", "localhost:8080")]).await,
+            Ok(host("localhost", Some(8080)))
+        );
+        assert_eq!(
+            allowed_host(None, &[("Host", "localhost")]).await,
+            Err(AllowedHostRejection::NotAllowed(
+                header::HOST,
+                String::from("localhost")
+            ))
+        );
+        assert_eq!(
+            allowed_host(None, &[("Host", "example.com"), ("Host", "example.com")]).await,
+            Err(AllowedHostRejection::Duplicate)
+        );
+        assert_eq!(
+            allowed_host(None, &[("Host", "user@example.com")]).await,
+            Err(AllowedHostRejection::Invalid(header::HOST))
+        );
+        assert_eq!(
+            allowed_host(None, &[]).await,
+            Err(AllowedHostRejection::Missing)
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_ignored_without_trust() {
+        let headers = [
+            ("Host", "example.com"),
+            ("Forwarded", "for=192.0.2.1;host=evil.example"),
+            ("X-Forwarded-Host", "evil.example"),
+        ];
+        assert_eq!(
+            allowed_host(None, &headers).await,
+            Ok(host("example.com", None))
+        );
+        let trusted = TrustedProxies::networks("192.168.0.0/16".parse().expect("???"));
+        assert_eq!(
+            allowed_host(Some(trusted), &headers).await,
+            Ok(host("example.com", None))
+        );
+    }
+
+    #[tokio::test]
+    async fn trusted_forwarded() {
+        let trusted = TrustedProxies::networks("10.0.0.0/8".parse().expect("???"));
+        assert_eq!(
+            allowed_host(
+                Some(trusted.clone()),
+                &[
+                    ("Host", "internal"),
+                    // The client made up the first hop; our edge proxy at 10.0.0.2 added the second.
+                    (
+                        "Forwarded",
+                        "for=198.51.100.1;host=evil.example, for=192.0.2.1;host=a.example.org, for=10.0.0.2"
+                    ),
+                ]
+            )
+            .await,
+            Ok(host("a.example.org", None))
+        );
+        assert_eq!(
+            allowed_host(
+                Some(trusted),
+                &[
+                    ("Host", "internal"),
+                    ("Forwarded", "for=10.0.0.2;host=evil.example")
+                ]
+            )
+            .await,
+            Err(AllowedHostRejection::NotAllowed(
+                header::FORWARDED,
+                String::from("evil.example")
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_trusted_hop() {
+        // Without a `for`, we can't tell whether 10.0.0.2 or the client sent `evil.example`.
+        let trusted = TrustedProxies::networks("10.0.0.0/8".parse().expect("???"));
+        assert_eq!(
+            allowed_host(
+                Some(trusted),
+                &[
+                    ("Host", "internal"),
+                    (
+                        "Forwarded",
+                        "for=192.0.2.1;host=example.com, for=10.0.0.2;host=evil.example, for=not-an-ip"
+                    ),
+                ]
+            )
+            .await,
+            Err(AllowedHostRejection::InvalidHop(HopHeader::Forwarded, 2))
+        );
+    }
+
+    #[tokio::test]
+    async fn trusted_x_forwarded() {
+        let trusted = TrustedProxies::hops(1).header(HopHeader::XForwardedFor);
+        assert_eq!(
+            allowed_host(
+                Some(trusted),
+                &[
+                    ("Host", "internal"),
+                    ("Forwarded", "for=192.0.2.1;host=evil.example"),
+                    ("X-Forwarded-For", "192.0.2.1"),
+                    ("X-Forwarded-Host", "example.com"),
+                    ("X-Forwarded-Port", "443"),
+                ]
+            )
+            .await,
+            Ok(host("example.com", Some(443)))
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_allowed_hosts() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .header("Host", "example.com")
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            AllowedHost::from_request_parts(&mut parts, &()).await,
+            Err(AllowedHostRejection::MissingAllowedHosts)
+        );
+    }
+}
@@ -0,0 +1,498 @@
+//! Works out who the client is, believing only the proxies we trust.
+//!
+//! Every hop in `Forwarded` and `X-Forwarded-For` is whatever the previous hop said it was, so
+//! the leftmost one is whatever the client wants it to be. Instead, we start from the address
+//! which actually connected to us, and walk leftwards for as long as the address we're at
+//! belongs to a proxy we trust.
+use crate::extract::forwarded::{Forwarded, ForwardedRejection, Interface};
+use crate::extract::rejection::{Rejection, RejectionKind, impl_into_response_via_rejection};
+use crate::extract::xforwardedfor::{XForwardedFor, XForwardedForRejection};
+use crate::network::NetworkSet;
+use crate::proxy;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts};
+use axum::http::header::{self, HeaderName};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Which header our proxies use to tell us who they're forwarding for. See also
+/// [`ForwardedPrecedence`](crate::extract::ForwardedPrecedence).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HopHeader {
+    /// `Forwarded`, using the `for` parameters.
+    #[default]
+    Forwarded,
+    /// `X-Forwarded-For`. In [`ForwardedPrecedence`](crate::extract::ForwardedPrecedence) and
+    /// [`ForwardedInfo`](crate::extract::ForwardedInfo), this stands for the whole
+    /// `X-Forwarded-*` family.
+    XForwardedFor,
+}
+
+impl HopHeader {
+    /// The header's name.
+    pub(crate) fn name(self) -> HeaderName {
+        match self {
+            Self::Forwarded => header::FORWARDED,
+            Self::XForwardedFor => X_FORWARDED_FOR_HEADER,
+        }
+    }
+}
+
+const X_FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// What a hop says about who it was forwarding for, as far as walking back goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HopFor {
+    Ip(IpAddr),
+    /// `unknown`, or an obfuscated node: the proxy wouldn't say.
+    Hidden,
+    /// The proxy didn't say.
+    Missing,
+    /// The proxy said something we couldn't make sense of.
+    Invalid,
+}
+
+impl HopFor {
+    pub(crate) fn of(r#for: Option<&Interface>) -> Self {
+        match r#for {
+            None => Self::Missing,
+            Some(Interface::Identifier(_)) => Self::Invalid,
+            Some(interface) => interface.ip().map_or(Self::Hidden, Self::Ip),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Trust {
+    Hops(usize),
+    Networks(NetworkSet),
+}
+
+/// The proxies in front of us whose word we'll take for who the client is.
+///
+/// Put it in the request's extensions, e.g. with `.layer(axum::Extension(trusted))`. Without
+/// it, no one is trusted and [`ClientIp`] is the address which connected to us.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustedProxies {
+    trust: Trust,
+    header: HopHeader,
+}
+
+impl Default for TrustedProxies {
+    fn default() -> Self {
+        Self::hops(0)
+    }
+}
+
+impl TrustedProxies {
+    /// There are exactly this many proxies in front of us, whatever their addresses.
+    pub fn hops(hops: usize) -> Self {
+        Self {
+            trust: Trust::Hops(hops),
+            header: HopHeader::default(),
+        }
+    }
+
+    /// Proxies with addresses in these networks.
+    pub fn networks(networks: NetworkSet) -> Self {
+        Self {
+            trust: Trust::Networks(networks),
+            header: HopHeader::default(),
+        }
+    }
+
+    /// Which header to read the hops from. The other is ignored, since our proxies won't have
+    /// touched it and so it's entirely up to the client.
+    pub fn header(mut self, header: HopHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Whether to believe the hop at `ip`, `depth` proxies away from us.
+    pub(crate) fn is_trusted(&self, ip: IpAddr, depth: usize) -> bool {
+        match &self.trust {
+            Trust::Hops(hops) => depth < *hops,
+            Trust::Networks(networks) => networks.contains(ip),
+        }
+    }
+
+    /// Walk the `hops` (leftmost first) back from `peer`, returning where we got to, and how
+    /// many of the hops (from the right) were added by proxies we trust. The walk stops at the
+    /// first trusted hop without an IP, so if any of them don't have one, it's the leftmost.
+    pub(crate) fn walk(&self, peer: IpAddr, hops: &[HopFor]) -> (IpAddr, usize) {
+        let mut client = peer;
+        let mut trusted = 0;
+        for (depth, hop) in hops.iter().rev().enumerate() {
+            if !self.is_trusted(client, depth) {
+                break;
+            }
+            trusted += 1;
+            match hop {
+                HopFor::Ip(ip) => client = *ip,
+                // A proxy we trust didn't know (or wouldn't say), so this is as far as we get.
+                _ => break,
+            }
+        }
+        (client, trusted)
+    }
+
+    /// Which header the hops come from.
+    pub(crate) fn hop_header(&self) -> HopHeader {
+        self.header
+    }
+}
+
+/// The client's IP, as best as we can tell from the proxies we trust.
+///
+/// Needs the connection's address, so serve with
+/// `into_make_service_with_connect_info::<SocketAddr>()` or
+/// `into_make_service_with_connect_info::<proxy::Addr>()`. See [`TrustedProxies`] for which
+/// hops are believed.
+///
+/// If a trusted proxy says `unknown` or gives an obfuscated node, that proxy is as far back as
+/// we can go. If it leaves `for` out, or sends something invalid, the request is rejected.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Extension, Router, routing::get};
+/// use axum_proxied::extract::{ClientIp, TrustedProxies};
+///
+/// async fn handler(client: ClientIp) -> String {
+///     client.ip().to_string()
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(TrustedProxies::networks(
+///         "10.0.0.0/8, fd00::/8".parse().unwrap(),
+///     )));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp {
+    ip: IpAddr,
+}
+
+impl ClientIp {
+    /// A new one...
+    pub fn new(ip: IpAddr) -> Self {
+        Self { ip }
+    }
+
+    /// The client's IP.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+/// Why we couldn't work out the client's IP.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientIpRejection {
+    /// We weren't told who connected to us; the service needs connect info.
+    MissingConnectInfo,
+    /// The hops came from a `Forwarded` header we couldn't parse.
+    Forwarded(ForwardedRejection),
+    /// The hops came from an `X-Forwarded-For` header we couldn't parse.
+    XForwardedFor(XForwardedForRejection),
+    /// A proxy we trust sent this hop (counting from `0`) in this header, without a `for` we
+    /// could make sense of.
+    InvalidHop(HopHeader, usize),
+}
+
+impl fmt::Display for ClientIpRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingConnectInfo => {
+                f.write_str("missing connect info, could not determine client IP")
+            }
+            Self::Forwarded(rejection) => rejection.fmt(f),
+            Self::XForwardedFor(rejection) => rejection.fmt(f),
+            Self::InvalidHop(source, index) => write!(
+                f,
+                "{} hop {index} from a trusted proxy has no valid `for`",
+                source.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClientIpRejection {}
+
+impl From<ClientIpRejection> for Rejection {
+    fn from(value: ClientIpRejection) -> Self {
+        match value {
+            ClientIpRejection::MissingConnectInfo => {
+                Rejection::new(RejectionKind::Misconfigured, None, value.to_string())
+            }
+            ClientIpRejection::Forwarded(rejection) => Rejection::from(rejection),
+            ClientIpRejection::XForwardedFor(rejection) => Rejection::from(rejection),
+            ClientIpRejection::InvalidHop(source, _) => Rejection::new(
+                RejectionKind::Invalid,
+                Some(source.name()),
+                value.to_string(),
+            ),
+        }
+    }
+}
+
+impl_into_response_via_rejection!(ClientIpRejection);
+
+/// The address which connected to us, from the PROXY header if there was one.
+pub(crate) fn peer_ip(parts: &Parts) -> Option<IpAddr> {
+    if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<proxy::Addr>>() {
+        return Some(addr.source().ip());
+    }
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = ClientIpRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(peer) = peer_ip(parts) else {
+            return Err(ClientIpRejection::MissingConnectInfo);
+        };
+        let trusted = parts
+            .extensions
+            .get::<TrustedProxies>()
+            .cloned()
+            .unwrap_or_default();
+        if !trusted.is_trusted(peer, 0) {
+            // Don't bother with the headers, they're whatever the client wants them to be.
+            return Ok(ClientIp::new(peer));
+        }
+        let hops: Vec<HopFor> = match trusted.header {
+            HopHeader::Forwarded => {
+                <Forwarded as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                    .await
+                    .map_err(ClientIpRejection::Forwarded)?
+                    .map(|forwarded| forwarded_hops(&forwarded))
+                    .unwrap_or_default()
+            }
+            HopHeader::XForwardedFor => {
+                <XForwardedFor as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                    .await
+                    .map_err(ClientIpRejection::XForwardedFor)?
+                    .map(|xforwarded| {
+                        let forwards = xforwarded.forwards().iter();
+                        forwards.map(|forward| HopFor::of(Some(forward))).collect()
+                    })
+                    .unwrap_or_default()
+            }
+        };
+        let (client, count) = trusted.walk(peer, &hops);
+        let leftmost = hops.len() - count;
+        if let Some(HopFor::Missing | HopFor::Invalid) = hops.get(leftmost).filter(|_| count > 0) {
+            return Err(ClientIpRejection::InvalidHop(trusted.header, leftmost));
+        }
+        Ok(ClientIp::new(client))
+    }
+}
+
+/// What each element of `forwarded` says about who it was for. Elements with anything invalid
+/// in them are [`HopFor::Invalid`], since we can't be sure what the proxy meant.
+pub(crate) fn forwarded_hops(forwarded: &Forwarded) -> Vec<HopFor> {
+    let forwards = forwarded.forwards().iter().enumerate();
+    forwards
+        .map(|(index, forward)| {
+            if forwarded.invalid().iter().any(|e| e.index() == index) {
+                return HopFor::Invalid;
+            }
+            HopFor::of(forward.r#for().as_ref())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn client_ip(
+        peer: &str,
+        trusted: Option<TrustedProxies>,
+        headers: &[(&str, &str)],
+    ) -> Result<ClientIp, ClientIpRejection> {
+        let mut builder = axum::http::request::Builder::new()
+            .method("GET")
+            .extension(ConnectInfo(peer.parse::<SocketAddr>().expect("???")));
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        if let Some(trusted) = trusted {
+            builder = builder.extension(trusted);
+        }
+        let (mut parts, _) = builder
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        ClientIp::from_request_parts(&mut parts, &()).await
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse::<IpAddr>().expect("???")
+    }
+
+    fn ten_slash_eight() -> TrustedProxies {
+        TrustedProxies::networks("10.0.0.0/8".parse().expect("could not parse networks"))
+    }
+
+    #[tokio::test]
+    async fn untrusted_peer() {
+        let client = client_ip(
+            "192.0.2.1:1234",
+            Some(ten_slash_eight()),
+            &[("Forwarded", "for=198.51.100.1")],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("192.0.2.1"))));
+    }
+
+    #[tokio::test]
+    async fn nobody_trusted_by_default() {
+        let client = client_ip("10.0.0.1:1234", None, &[("Forwarded", "for=198.51.100.1")]).await;
+        assert_eq!(client, Ok(ClientIp::new(ip("10.0.0.1"))));
+    }
+
+    #[tokio::test]
+    async fn spoofed_leftmost_hop() {
+        // The client made up 203.0.113.9, our proxies at 10.0.0.2 and 10.0.0.1 appended the rest.
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight()),
+            &[(
+                "Forwarded",
+                "for=203.0.113.9, for=198.51.100.1, for=10.0.0.2",
+            )],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.1"))));
+    }
+
+    #[tokio::test]
+    async fn hops() {
+        // The peer is one proxy, 192.0.2.2 the other.
+        let client = client_ip(
+            "192.0.2.1:1234",
+            Some(TrustedProxies::hops(2).header(HopHeader::XForwardedFor)),
+            &[("X-Forwarded-For", "203.0.113.9, 198.51.100.1, 192.0.2.2")],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.1"))));
+    }
+
+    #[tokio::test]
+    async fn unclosed_quote_on_earlier_line() {
+        // The client's broken line mustn't swallow the one our proxy added.
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(TrustedProxies::hops(1)),
+            &[
+                ("Forwarded", r#"for="x"#),
+                ("Forwarded", "for=198.51.100.7"),
+            ],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.7"))));
+    }
+
+    #[tokio::test]
+    async fn fewer_hops_than_trusted() {
+        let client = client_ip(
+            "192.0.2.1:1234",
+            Some(TrustedProxies::hops(5).header(HopHeader::XForwardedFor)),
+            &[("X-Forwarded-For", "198.51.100.1")],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.1"))));
+    }
+
+    #[tokio::test]
+    async fn other_header_ignored() {
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight().header(HopHeader::XForwardedFor)),
+            &[
+                ("Forwarded", "for=203.0.113.9"),
+                ("X-Forwarded-For", "198.51.100.1"),
+            ],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.1"))));
+    }
+
+    #[tokio::test]
+    async fn hidden_hop() {
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight()),
+            &[("Forwarded", "for=203.0.113.9, for=_hidden, for=10.0.0.2")],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("10.0.0.2"))));
+    }
+
+    #[tokio::test]
+    async fn invalid_trusted_hop() {
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight()),
+            &[("Forwarded", "for=198.51.100.1, for=not-an-ip")],
+        )
+        .await;
+        assert_eq!(
+            client,
+            Err(ClientIpRejection::InvalidHop(HopHeader::Forwarded, 1))
+        );
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight()),
+            &[("Forwarded", "for=198.51.100.1, proto=https")],
+        )
+        .await;
+        assert_eq!(
+            client,
+            Err(ClientIpRejection::InvalidHop(HopHeader::Forwarded, 1))
+        );
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight().header(HopHeader::XForwardedFor)),
+            &[("X-Forwarded-For", "198.51.100.1, 10.0.0.2, not-an-ip")],
+        )
+        .await;
+        assert_eq!(
+            client,
+            Err(ClientIpRejection::InvalidHop(HopHeader::XForwardedFor, 2))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_untrusted_hop() {
+        let client = client_ip(
+            "10.0.0.1:1234",
+            Some(ten_slash_eight()),
+            &[("Forwarded", "for=not-an-ip, for=198.51.100.1")],
+        )
+        .await;
+        assert_eq!(client, Ok(ClientIp::new(ip("198.51.100.1"))));
+    }
+
+    #[tokio::test]
+    async fn missing_connect_info() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            ClientIp::from_request_parts(&mut parts, &()).await,
+            Err(ClientIpRejection::MissingConnectInfo)
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Puts back together the URL the client actually used, e.g. for OAuth redirect URIs, `Location`
+//! headers and canonical links.
+use crate::extract::allowedhost::{AllowedHostRejection, AllowedHosts, resolve, trusted_hops};
+use crate::extract::clientip::{TrustedProxies, peer_ip};
+use crate::extract::forwarded::Protocol;
+use crate::extract::rejection::{Rejection, RejectionKind, impl_into_response_via_rejection};
+use crate::extract::xforwardedprefix::{XForwardedPrefix, XForwardedPrefixRejection};
+use axum::body::Body;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts, OriginalUri};
+use axum::http::Uri;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// The URL the client used, as best as we can tell from the proxies we trust.
+///
+/// The scheme, host and port are from the client's closest hop added by [`TrustedProxies`]
+/// that says, in whichever header they use (`Forwarded`, or `X-Forwarded-Proto`, `-Host` and
+/// `-Port`). Otherwise, they're from the request: the URI, or the `Host` header and `http`. The
+/// path and query are from the original URI, before any [`Router::nest`](axum::Router::nest)
+/// took off a prefix, after the [`XForwardedPrefix`] if the peer is a trusted proxy. Default
+/// ports are left out.
+///
+/// The host has to be one of the [`AllowedHosts`] in the request's extensions, since the URL
+/// is bound to end up somewhere that matters. Without them, the request is rejected as
+/// misconfigured.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::http::header::LOCATION;
+/// use axum::{Extension, Router, routing::get};
+/// use axum_proxied::extract::{AllowedHosts, ExternalUrl};
+///
+/// async fn handler(url: ExternalUrl) -> [(axum::http::HeaderName, String); 1] {
+///     [(LOCATION, format!("{url}?done"))]
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(AllowedHosts::new(["example.com"]).unwrap()));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExternalUrl {
+    uri: Uri,
+}
+
+impl ExternalUrl {
+    /// A new one...
+    pub fn new(uri: Uri) -> Self {
+        Self { uri }
+    }
+
+    /// The whole URL.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+}
+
+impl fmt::Display for ExternalUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.uri.fmt(f)
+    }
+}
+
+/// Why we couldn't put the URL back together.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExternalUrlRejection {
+    /// See [`AllowedHostRejection`].
+    Host(AllowedHostRejection),
+    /// See [`XForwardedPrefixRejection`].
+    Prefix(XForwardedPrefixRejection),
+    /// The pieces don't make a URL, e.g. the protocol isn't a scheme.
+    Invalid,
+}
+
+impl fmt::Display for ExternalUrlRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host(rejection) => rejection.fmt(f),
+            Self::Prefix(rejection) => rejection.fmt(f),
+            Self::Invalid => f.write_str("could not build external URL"),
+        }
+    }
+}
+
+impl std::error::Error for ExternalUrlRejection {}
+
+impl From<ExternalUrlRejection> for Rejection {
+    fn from(value: ExternalUrlRejection) -> Self {
+        match value {
+            ExternalUrlRejection::Host(rejection) => Rejection::from(rejection),
+            ExternalUrlRejection::Prefix(rejection) => Rejection::from(rejection),
+            ExternalUrlRejection::Invalid => {
+                Rejection::new(RejectionKind::Invalid, None, value.to_string())
+            }
+        }
+    }
+}
+
+impl_into_response_via_rejection!(ExternalUrlRejection);
+
+impl<S> FromRequestParts<S> for ExternalUrl
+where
+    S: Send + Sync,
+{
+    type Rejection = ExternalUrlRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(allowed) = parts.extensions.get::<AllowedHosts>().cloned() else {
+            return Err(ExternalUrlRejection::Host(
+                AllowedHostRejection::MissingAllowedHosts,
+            ));
+        };
+        let (hops, source) = trusted_hops(parts, state)
+            .await
+            .map_err(ExternalUrlRejection::Host)?;
+        let host = resolve(parts, &hops, source, &allowed).map_err(ExternalUrlRejection::Host)?;
+        let scheme = match hops.iter().find_map(|hop| hop.proto().clone()) {
+            Some(Protocol::Http) => String::from("http"),
+            Some(Protocol::Https) => String::from("https"),
+            Some(Protocol::Other(other)) => other.to_ascii_lowercase(),
+            None => parts
+                .uri
+                .scheme_str()
+                .map(str::to_ascii_lowercase)
+                .unwrap_or_else(|| String::from("http")),
+        };
+        let authority = match (scheme.as_str(), host.port()) {
+            ("http", Some(80)) | ("https", Some(443)) => String::from(host.host()),
+            _ => host.to_string(),
+        };
+        let peer_trusted = parts
+            .extensions
+            .get::<TrustedProxies>()
+            .zip(peer_ip(parts))
+            .is_some_and(|(trusted, peer)| trusted.is_trusted(peer, 0));
+        let prefix = if peer_trusted {
+            <XForwardedPrefix as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                .await
+                .map_err(ExternalUrlRejection::Prefix)?
+        } else {
+            None
+        };
+        let uri = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => &parts.uri,
+        };
+        let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+        let path_and_query = match prefix {
+            Some(prefix) => prefix.join(path_and_query),
+            None => String::from(path_and_query),
+        };
+        Uri::builder()
+            .scheme(scheme.as_str())
+            .authority(authority)
+            .path_and_query(path_and_query)
+            .build()
+            .map(ExternalUrl::new)
+            .map_err(|_| ExternalUrlRejection::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{HopHeader, TrustedProxies};
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    async fn external_url(
+        uri: &str,
+        trusted: Option<TrustedProxies>,
+        headers: &[(&str, &str)],
+    ) -> Result<String, ExternalUrlRejection> {
+        let mut builder = axum::http::request::Builder::new()
+            .method("GET")
+            .uri(uri)
+            .extension(ConnectInfo(
+                "10.0.0.1:1234".parse::<SocketAddr>().expect("???"),
+            ))
+            .extension(AllowedHosts::new(["example.com"]).expect("???"));
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        if let Some(trusted) = trusted {
+            builder = builder.extension(trusted);
+        }
+        let (mut parts, _) = builder
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        ExternalUrl::from_request_parts(&mut parts, &())
+            .await
+            .map(|url| url.to_string())
+    }
+
+    #[tokio::test]
+    async fn from_request() {
+        assert_eq!(
+            external_url("/a/b?c=d", None, &[("Host", "example.com:8080")]).await,
+            Ok(String::from("http://example.com:8080/a/b?c=d"))
+        );
+        assert_eq!(
+            external_url("https://example.com:443/", None, &[]).await,
+            Ok(String::from("https://example.com/"))
+        );
+        // Only trusted proxies get a say.
+        assert_eq!(
+            external_url(
+                "/",
+                None,
+                &[
+                    ("Host", "example.com"),
+                    ("Forwarded", "host=evil.example;proto=https"),
+                    ("X-Forwarded-Prefix", "/evil"),
+                ]
+            )
+            .await,
+            Ok(String::from("http://example.com/"))
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded() {
+        let trusted = TrustedProxies::hops(1);
+        assert_eq!(
+            external_url(
+                "/callback",
+                Some(trusted),
+                &[
+                    ("Host", "internal:3000"),
+                    ("Forwarded", "for=192.0.2.1;host=example.com;proto=https")
+                ]
+            )
+            .await,
+            Ok(String::from("https://example.com/callback"))
+        );
+    }
+
+    #[tokio::test]
+    async fn x_forwarded() {
+        let trusted = TrustedProxies::hops(1).header(HopHeader::XForwardedFor);
+        assert_eq!(
+            external_url(
+                "/callback",
+                Some(trusted),
+                &[
+                    ("Host", "internal:3000"),
+                    ("X-Forwarded-For", "192.0.2.1"),
+                    ("X-Forwarded-Proto", "https"),
+                    ("X-Forwarded-Host", "example.com"),
+                    ("X-Forwarded-Port", "8443"),
+                    ("X-Forwarded-Prefix", "/api/billing"),
+                ]
+            )
+            .await,
+            Ok(String::from(
+                "https://example.com:8443/api/billing/callback"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn original_uri() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .uri("/nested")
+            .header("Host", "example.com")
+            .extension(OriginalUri(Uri::from_static("/api/nested")))
+            .extension(AllowedHosts::new(["example.com"]).expect("???"))
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            ExternalUrl::from_request_parts(&mut parts, &())
+                .await
+                .map(|url| url.to_string()),
+            Ok(String::from("http://example.com/api/nested"))
+        );
+    }
+
+    #[tokio::test]
+    async fn allowed_hosts() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .header("Host", "evil.example")
+            .extension(AllowedHosts::new(["example.com"]).expect("???"))
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert!(matches!(
+            ExternalUrl::from_request_parts(&mut parts, &()).await,
+            Err(ExternalUrlRejection::Host(
+                AllowedHostRejection::NotAllowed(..)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_allowed_hosts() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .header("Host", "example.com")
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        let rejection = ExternalUrl::from_request_parts(&mut parts, &())
+            .await
+            .expect_err("not rejected");
+        assert_eq!(
+            rejection,
+            ExternalUrlRejection::Host(AllowedHostRejection::MissingAllowedHosts)
+        );
+        assert_eq!(
+            Rejection::from(rejection).kind(),
+            RejectionKind::Misconfigured
+        );
+    }
+}
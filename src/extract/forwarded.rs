@@ -1,17 +1,28 @@
 //! Support for HTTP Header [`Forwarded`][mdn].
 //!
 //! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Forwarded
+use crate::Strictness;
+use crate::extract::header_lines;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
 use axum::body::Body;
 use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
+use axum::http::header::{HeaderValue, InvalidHeaderValue};
 use axum::http::request::Parts;
-use axum::http::{StatusCode, header};
 use axum::response::{IntoResponse, Response};
 use std::convert::Infallible;
-use std::net::{IpAddr, SocketAddr};
+use std::fmt::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 
+mod tokenizer;
+
+pub use self::tokenizer::SyntaxError;
+
 /// The protocol which initiated the request.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Protocol {
     /// Regular HTTP
     Http,
@@ -35,26 +46,41 @@ impl FromStr for Protocol {
     }
 }
 
-/// The interface we're forwarding from/to.
-///
-/// Once
-/// [`std::net::SocketAddr::parse_ascii`](https://doc.rust-lang.org/std/net/enum.SocketAddr.html#method.parse_ascii)
-/// is out of nightly this should become _much_ simpler.
+/// The interface we're forwarding from/to, i.e. a `node` from
+/// [RFC 7239 §6](https://www.rfc-editor.org/rfc/rfc7239#section-6).
 ///
-/// At that point
-///
-/// 1. Try parsing for IPv4
-/// 1. Trim quotes,
-/// 1. Try parsing for IPv6
-/// 1. Else, it's probably an identifier.
-#[derive(Debug, PartialEq, Eq)]
+/// IPs without a port are given port `0`.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Interface {
-    /// Probably a hostname or an internal name of some sort.
+    /// Something which isn't a valid node name. Only produced when parsing leniently.
     Identifier(String),
     /// The parsed IP.
     Socket(SocketAddr),
     /// A literal `unknown`.
     Unknown,
+    /// An obfuscated node name, e.g. `_hidden`, and its port if it had one.
+    Obfuscated {
+        /// The name, including the leading `_`.
+        node: String,
+        /// The port, if there was one.
+        port: Option<Port>,
+    },
+    /// A real IP, with an obfuscated port, e.g. `[2001:db8::1]:_abc`.
+    ObfuscatedPort {
+        /// The IP.
+        ip: IpAddr,
+        /// The port, including the leading `_`.
+        port: String,
+    },
+}
+
+/// The port of an obfuscated node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Port {
+    /// A regular port number.
+    Number(u16),
+    /// An obfuscated port, including the leading `_`.
+    Obfuscated(String),
 }
 
 impl From<SocketAddr> for Interface {
@@ -63,66 +89,182 @@ impl From<SocketAddr> for Interface {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Parser {
-    Begin,
-    OpenBracket,
-    CloseBracket,
-    Colon,
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http => f.write_str("http"),
+            Self::Https => f.write_str("https"),
+            Self::Other(other) => f.write_str(other),
+        }
+    }
 }
 
-impl FromStr for Interface {
-    type Err = Infallible;
+/// Writes the node without quotes, e.g. `[2001:db8::1]:80`. Port `0` is left off.
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(identifier) => f.write_str(identifier),
+            Self::Socket(socket) if socket.port() == 0 => match socket.ip() {
+                IpAddr::V4(ip) => write!(f, "{ip}"),
+                IpAddr::V6(ip) => write!(f, "[{ip}]"),
+            },
+            Self::Socket(socket) => write!(f, "{socket}"),
+            Self::Unknown => f.write_str("unknown"),
+            Self::Obfuscated { node, port: None } => f.write_str(node),
+            Self::Obfuscated {
+                node,
+                port: Some(port),
+            } => write!(f, "{node}:{port}"),
+            Self::ObfuscatedPort {
+                ip: IpAddr::V4(ip),
+                port,
+            } => write!(f, "{ip}:{port}"),
+            Self::ObfuscatedPort {
+                ip: IpAddr::V6(ip),
+                port,
+            } => write!(f, "[{ip}]:{port}"),
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.eq_ignore_ascii_case("unknown") || s.eq_ignore_ascii_case(r#""unknown""#) {
-            return Ok(Self::Unknown);
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(port) => write!(f, "{port}"),
+            Self::Obfuscated(port) => f.write_str(port),
+        }
+    }
+}
+
+/// A node name which isn't an IP, an obfuscated name, nor `unknown`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidNode;
+
+impl fmt::Display for InvalidNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid node name")
+    }
+}
+
+impl std::error::Error for InvalidNode {}
+
+/// `"_" 1*( ALPHA / DIGIT / "." / "_" / "-")`
+fn is_obfuscated(s: &str) -> bool {
+    s.strip_prefix('_').is_some_and(|rest| {
+        !rest.is_empty()
+            && rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    })
+}
+
+enum NodeName {
+    Ip(IpAddr),
+    Unknown,
+    Obfuscated(String),
+}
+
+fn parse_node_port(s: &str) -> Option<Port> {
+    if is_obfuscated(s) {
+        return Some(Port::Obfuscated(String::from(s)));
+    }
+    if s.is_empty() || s.len() > 5 || !s.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse::<u16>().ok().map(Port::Number)
+}
+
+/// `nodename [ ":" node-port ]`, to the letter.
+fn parse_node(s: &str) -> Option<Interface> {
+    let (name, port) = match s.strip_prefix('[') {
+        Some(bracketed) => {
+            let (ip, rest) = bracketed.split_once(']')?;
+            let ip = ip.parse::<Ipv6Addr>().ok()?;
+            let port = match rest {
+                "" => None,
+                rest => Some(rest.strip_prefix(':')?),
+            };
+            (NodeName::Ip(IpAddr::V6(ip)), port)
+        }
+        None => {
+            let (name, port) = match s.split_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (s, None),
+            };
+            let name = if name.eq_ignore_ascii_case("unknown") {
+                NodeName::Unknown
+            } else if is_obfuscated(name) {
+                NodeName::Obfuscated(String::from(name))
+            } else {
+                NodeName::Ip(IpAddr::V4(name.parse::<Ipv4Addr>().ok()?))
+            };
+            (name, port)
+        }
+    };
+    let port = match port {
+        Some(port) => Some(parse_node_port(port)?),
+        None => None,
+    };
+    Some(match (name, port) {
+        (NodeName::Unknown, _) => Interface::Unknown,
+        (NodeName::Ip(ip), None) => Interface::Socket(SocketAddr::from((ip, 0))),
+        (NodeName::Ip(ip), Some(Port::Number(port))) => {
+            Interface::Socket(SocketAddr::from((ip, port)))
+        }
+        (NodeName::Ip(ip), Some(Port::Obfuscated(port))) => Interface::ObfuscatedPort { ip, port },
+        (NodeName::Obfuscated(node), port) => Interface::Obfuscated { node, port },
+    })
+}
+
+impl Interface {
+    /// Parse a node, with or without the quotes around it.
+    ///
+    /// When strict, anything which isn't an IP, an obfuscated name, or `unknown` is an error.
+    /// When lenient, bare IPv6 addresses are accepted, and anything else becomes an
+    /// [`Interface::Identifier`].
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, InvalidNode> {
+        let s = s.trim();
+        let s = s
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap_or(s);
+        if let Some(node) = parse_node(s) {
+            return Ok(node);
         }
-        let s_trimmed = s.trim().trim_matches('"');
-        if let Ok(simple) = s_trimmed.parse::<SocketAddr>() {
+        if strictness == Strictness::Strict {
+            return Err(InvalidNode);
+        }
+        if let Ok(simple) = s.parse::<SocketAddr>() {
             return Ok(Self::Socket(simple));
         };
-        if let Ok(simple) = s_trimmed.parse::<IpAddr>() {
+        if let Ok(simple) = s.parse::<IpAddr>() {
             return Ok(Self::Socket(SocketAddr::from((simple, 0))));
         };
-        let mut ip_start = 0;
-        let mut ip_end = 0;
-        let mut port_start = 0;
-        let mut state = Parser::Begin;
-        let chars = s_trimmed.chars().enumerate();
-        for (index, c) in chars {
-            match (c, &state) {
-                ('[', Parser::Begin) => {
-                    ip_start = index + 1;
-                    state = Parser::OpenBracket;
-                }
-                (']', Parser::OpenBracket) => {
-                    ip_end = index;
-                    state = Parser::CloseBracket;
-                }
-                (':', Parser::CloseBracket) => {
-                    port_start = index + 1;
-                    state = Parser::Colon;
-                }
-                _ => {}
-            }
-        }
-        let ip: Option<IpAddr> = s_trimmed
-            .get(ip_start..ip_end)
-            .and_then(|ip| ip.parse::<IpAddr>().ok());
-        let port: Option<u16> = s_trimmed
-            .get(port_start..)
-            .and_then(|p| p.parse::<u16>().ok());
-        match (ip, port) {
-            (Some(ip), Some(port)) => Ok(Self::Socket(SocketAddr::from((ip, port)))),
-            (Some(ip), None) => Ok(Self::Socket(SocketAddr::from((ip, 0)))),
-            _ => Ok(Self::Identifier(String::from(s_trimmed.trim_matches('"')))),
+        Ok(Self::Identifier(String::from(s)))
+    }
+
+    /// The IP, if we know it.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Socket(socket) => Some(socket.ip()),
+            Self::ObfuscatedPort { ip, .. } => Some(*ip),
+            Self::Identifier(_) | Self::Unknown | Self::Obfuscated { .. } => None,
         }
     }
 }
 
+/// Parses leniently, see [`Interface::parse`].
+impl FromStr for Interface {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s, Strictness::Lenient)
+            .unwrap_or_else(|_| Self::Identifier(String::from(s))))
+    }
+}
+
 /// A single "forwarded" entry. All fields are optional, as per the spec.
-#[derive(Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Forward {
     /// The forwarder (proxy server).
     by: Option<Interface>,
@@ -132,6 +274,8 @@ pub struct Forward {
     host: Option<String>,
     /// The protocol used during this forward.
     proto: Option<Protocol>,
+    /// Any other parameters, with their names as sent.
+    extensions: Vec<(String, String)>,
 }
 
 impl Forward {
@@ -147,9 +291,16 @@ impl Forward {
             r#for,
             host,
             proto,
+            extensions: vec![],
         }
     }
 
+    /// Add an extension parameter.
+    pub fn with_extension(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extensions.push((name.into(), value.into()));
+        self
+    }
+
     /// Upstream proxy.
     pub fn by(&self) -> &Option<Interface> {
         &self.by
@@ -169,6 +320,45 @@ impl Forward {
     pub fn proto(&self) -> &Option<Protocol> {
         &self.proto
     }
+
+    /// Parameters other than `by`, `for`, `host` and `proto`, in the order they were sent.
+    pub fn extensions(&self) -> &Vec<(String, String)> {
+        &self.extensions
+    }
+
+    /// The value of an extension parameter. Names are case-insensitive.
+    pub fn extension(&self, name: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set a parameter from the header. Lenient parsing may still set a value which is in error.
+    fn set(&mut self, name: &str, value: String, strictness: Strictness) -> Result<(), ErrorKind> {
+        let node = if name.eq_ignore_ascii_case("by") {
+            &mut self.by
+        } else if name.eq_ignore_ascii_case("for") {
+            &mut self.r#for
+        } else if name.eq_ignore_ascii_case("host") {
+            self.host = Some(value);
+            return Ok(());
+        } else if name.eq_ignore_ascii_case("proto") {
+            self.proto = value.parse::<Protocol>().ok();
+            return Ok(());
+        } else {
+            self.extensions.push((String::from(name), value));
+            return Ok(());
+        };
+        let parsed = Interface::parse(&value, strictness).map_err(|_| ErrorKind::InvalidNode)?;
+        let valid = !matches!(parsed, Interface::Identifier(_));
+        *node = Some(parsed);
+        if valid {
+            Ok(())
+        } else {
+            Err(ErrorKind::InvalidNode)
+        }
+    }
 }
 
 /// Get the contents of the `Forwarded` header.
@@ -182,33 +372,249 @@ impl Forward {
 ///     todo!()
 /// }
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+///
+/// By default, pairs which don't parse are skipped, and noted in [`Forwarded::invalid`]. With
+/// [`Strictness::Strict`] in the request's extensions, they reject the request instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Forwarded {
     forwards: Vec<Forward>,
+    invalid: Vec<InvalidElement>,
 }
 
 impl Forwarded {
     /// A new one...
     pub fn new(forwards: Vec<Forward>) -> Self {
-        Self { forwards }
+        Self {
+            forwards,
+            invalid: vec![],
+        }
     }
 
     /// A list of [`Forward`]s.
     pub fn forwards(&self) -> &Vec<Forward> {
         &self.forwards
     }
+
+    /// Problems we skipped over while leniently parsing.
+    pub fn invalid(&self) -> &Vec<InvalidElement> {
+        &self.invalid
+    }
+
+    /// Parse the value of a `Forwarded` header. Quoted values may contain `,`, `;` and `=`, and
+    /// have their escapes undone.
+    ///
+    /// When strict, the first problem is returned as an error. When lenient, problems are
+    /// collected in [`Forwarded::invalid`] and whatever did parse is kept.
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, InvalidElement> {
+        Self::parse_lines([s], strictness)
+    }
+
+    /// Like [`Forwarded::parse`], but for a header sent as several lines. Each line is parsed on
+    /// its own, so a broken one can't run into the next. Element indices carry on across lines.
+    pub fn parse_lines<'a>(
+        lines: impl IntoIterator<Item = &'a str>,
+        strictness: Strictness,
+    ) -> Result<Self, InvalidElement> {
+        let mut forwarded = Forwarded::new(vec![]);
+        let elements = lines.into_iter().flat_map(tokenizer::elements);
+        for (index, element) in elements.enumerate() {
+            let mut forward = Forward::default();
+            let mut seen: Vec<&str> = vec![];
+            for pair in tokenizer::pairs(element, strictness) {
+                let result = match pair {
+                    Err(e) => Err((e.name, ErrorKind::Syntax(e.error))),
+                    // Each parameter may only appear once per element.
+                    Ok(pair) if seen.iter().any(|s| s.eq_ignore_ascii_case(pair.name)) => {
+                        Err((pair.name, ErrorKind::Duplicate))
+                    }
+                    Ok(tokenizer::Pair { name, value }) => {
+                        seen.push(name);
+                        forward
+                            .set(name, value, strictness)
+                            .map_err(|kind| (name, kind))
+                    }
+                };
+                let Err((name, kind)) = result else {
+                    continue;
+                };
+                let invalid = InvalidElement {
+                    index,
+                    parameter: String::from(name),
+                    kind,
+                };
+                if strictness == Strictness::Strict {
+                    return Err(invalid);
+                }
+                forwarded.invalid.push(invalid);
+            }
+            forwarded.forwards.push(forward);
+        }
+        Ok(forwarded)
+    }
+}
+
+/// Writes the element as it would appear in the header, e.g. `for="[2001:db8::1]:80";proto=https`.
+/// Values are quoted when they aren't tokens.
+impl fmt::Display for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by = self.by.as_ref().map(|by| ("by", by.to_string()));
+        let r#for = self.r#for.as_ref().map(|r#for| ("for", r#for.to_string()));
+        let host = self.host.as_ref().map(|host| ("host", host.clone()));
+        let proto = self
+            .proto
+            .as_ref()
+            .map(|proto| ("proto", proto.to_string()));
+        let extensions = self
+            .extensions
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()));
+        let pairs = [by, r#for, host, proto]
+            .into_iter()
+            .flatten()
+            .chain(extensions);
+        for (index, (name, value)) in pairs.enumerate() {
+            if index != 0 {
+                f.write_char(';')?;
+            }
+            write!(f, "{name}=")?;
+            tokenizer::write_value(f, &value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the whole header value, with elements separated by `, `.
+impl fmt::Display for Forwarded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, forward) in self.forwards.iter().enumerate() {
+            if index != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{forward}")?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! impl_try_from_for_header_value {
+    ($($ty:ty),+) => {
+        $(
+            /// Fails if something we're holding on to can't go in a header, e.g. control
+            /// characters in a `host`.
+            impl TryFrom<&$ty> for HeaderValue {
+                type Error = InvalidHeaderValue;
+
+                fn try_from(value: &$ty) -> Result<Self, Self::Error> {
+                    HeaderValue::try_from(value.to_string())
+                }
+            }
+        )+
+    };
+}
+
+impl_try_from_for_header_value!(Forwarded, Forward, Interface, Protocol);
+
+/// Parses leniently, see [`Forwarded::parse`].
+impl FromStr for Forwarded {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s, Strictness::Lenient).unwrap_or_else(|_| Forwarded::new(vec![])))
+    }
+}
+
+/// What was wrong with a parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// It didn't fit the grammar.
+    Syntax(SyntaxError),
+    /// It already appeared in the same element.
+    Duplicate,
+    /// A `by` or `for` which isn't an IP, an obfuscated name, nor `unknown`.
+    InvalidNode,
+}
+
+/// A parameter in a `Forwarded` element which we couldn't make sense of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidElement {
+    index: usize,
+    parameter: String,
+    kind: ErrorKind,
+}
+
+impl InvalidElement {
+    /// Which element it was in, counting from zero. Lines up with [`Forwarded::forwards`].
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The parameter's name, or the whole pair if it didn't have a usable name.
+    pub fn parameter(&self) -> &str {
+        &self.parameter
+    }
+
+    /// What was wrong with it.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for InvalidElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            ErrorKind::Syntax(SyntaxError::MissingEquals) => "missing `=`",
+            ErrorKind::Syntax(SyntaxError::InvalidName) => "invalid name",
+            ErrorKind::Syntax(SyntaxError::InvalidValue) => "invalid value",
+            ErrorKind::Syntax(SyntaxError::UnterminatedQuote) => "unterminated quoted-string",
+            ErrorKind::Duplicate => "duplicate parameter",
+            ErrorKind::InvalidNode => "invalid node name",
+        };
+        write!(
+            f,
+            "Forwarded element {}, parameter `{}`: {reason}",
+            self.index, self.parameter
+        )
+    }
 }
 
-/// The header's gotta be at least UTF-8.
+impl std::error::Error for InvalidElement {}
+
+/// Why we couldn't extract the `Forwarded` header.
 #[derive(Debug, PartialEq, Eq)]
-pub struct ForwardedRejection(&'static str);
+pub enum ForwardedRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// Part of the header didn't parse, and we're being strict about it.
+    Invalid(InvalidElement),
+}
 
-impl IntoResponse for ForwardedRejection {
-    fn into_response(self) -> Response<Body> {
-        (StatusCode::BAD_REQUEST, self.0).into_response()
+impl fmt::Display for ForwardedRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing Forwarded header"),
+            Self::NotAString => f.write_str("could not parse Forwarded header into string"),
+            Self::Invalid(invalid) => invalid.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ForwardedRejection {}
+
+impl From<ForwardedRejection> for Rejection {
+    fn from(value: ForwardedRejection) -> Self {
+        let kind = match value {
+            ForwardedRejection::Missing => RejectionKind::Missing,
+            ForwardedRejection::NotAString => RejectionKind::NotAString,
+            ForwardedRejection::Invalid(_) => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(header::FORWARDED), value.to_string())
     }
 }
 
+impl_into_response_via_rejection!(ForwardedRejection);
+
 impl<S> OptionalFromRequestParts<S> for Forwarded
 where
     S: Send + Sync,
@@ -219,43 +625,24 @@ where
         parts: &mut Parts,
         _state: &S,
     ) -> Result<Option<Self>, Self::Rejection> {
-        let Some(header_raw) = parts.headers.get(header::FORWARDED) else {
-            return Ok(None);
-        };
-        let Ok(header_str) = header_raw.to_str() else {
-            return Err(ForwardedRejection("could not parse header into string"));
+        let lines = match header_lines(&parts.headers, header::FORWARDED) {
+            Ok(Some(lines)) => lines,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(ForwardedRejection::NotAString),
         };
-        let mut forwards = vec![];
-        let forwards_raw = header_str
-            .split(",")
-            .map(|f| f.split(";").map(|p| p.split("=")));
-        for mut forward_raw in forwards_raw {
-            let mut by: Option<Interface> = None;
-            let mut r#for: Option<Interface> = None;
-            let mut host: Option<String> = None;
-            let mut proto: Option<Protocol> = None;
-            for mut params in forward_raw.by_ref() {
-                while let (Some(keyword), Some(value)) = (
-                    params.next().map(|s| s.trim()),
-                    params.next().map(|s| s.trim()),
-                ) {
-                    if keyword.eq_ignore_ascii_case("by") {
-                        by = value.trim().parse::<Interface>().ok();
-                    } else if keyword.eq_ignore_ascii_case("for") {
-                        r#for = value.trim().parse::<Interface>().ok();
-                    } else if keyword.eq_ignore_ascii_case("host") {
-                        host = Some(String::from(value.trim()));
-                    } else if keyword.eq_ignore_ascii_case("proto") {
-                        proto = value.trim().parse::<Protocol>().ok();
-                    }
-                }
-            }
-            forwards.push(Forward::new(by, r#for, host, proto));
-        }
-        Ok(Some(Forwarded::new(forwards)))
+        let strictness = parts
+            .extensions
+            .get::<Strictness>()
+            .copied()
+            .unwrap_or(Strictness::Lenient);
+        Forwarded::parse_lines(lines, strictness)
+            .map(Some)
+            .map_err(ForwardedRejection::Invalid)
     }
 }
 
+impl_required_from_request_parts!(Forwarded, ForwardedRejection::Missing);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -286,11 +673,365 @@ mod tests {
                         Some(Protocol::Https),
                     ),
                     Forward::new(None, "5.5.5.5:444".parse::<Interface>().ok(), None, None,),
-                ]
+                ],
+                invalid: vec![],
             }
         );
     }
 
+    #[tokio::test]
+    async fn split_headers() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("Forwarded", "for=192.0.2.60")
+            .header("X-Other", "whatever")
+            .header("Forwarded", r#"for="[2001:db8::1]", for=_hidden"#)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        let forwarded = Forwarded::from_request_parts(&mut parts, &())
+            .await
+            .expect("could not parse HTTP headers")
+            .expect("could not parse Forwarded header");
+        let fors: Vec<String> = forwarded
+            .forwards()
+            .iter()
+            .map(|forward| forward.r#for().as_ref().expect("no for").to_string())
+            .collect();
+        assert_eq!(fors, vec!["192.0.2.60", "[2001:db8::1]", "_hidden"]);
+    }
+
+    #[tokio::test]
+    async fn split_headers_not_a_string() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("Forwarded", "for=192.0.2.60")
+            .header("Forwarded", &b"for=\xff"[..])
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            Forwarded::from_request_parts(&mut parts, &()).await,
+            Err(ForwardedRejection::NotAString)
+        );
+    }
+
+    #[tokio::test]
+    async fn unclosed_quote_split_headers() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("Forwarded", r#"for="x"#)
+            .header("Forwarded", "for=198.51.100.7")
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        let forwarded = Forwarded::from_request_parts(&mut parts, &())
+            .await
+            .expect("could not parse HTTP headers")
+            .expect("could not parse Forwarded header");
+        assert_eq!(
+            forwarded.forwards()[1].r#for(),
+            &Some(Interface::Socket("198.51.100.7:0".parse().expect("???")))
+        );
+        let invalid = &forwarded.invalid()[0];
+        assert_eq!(invalid.index(), 0);
+        assert_eq!(
+            invalid.kind(),
+            ErrorKind::Syntax(SyntaxError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn quoted_values() {
+        let forwarded = r#"for="[2001:db8:cafe::17]:4711";host="a,b;c=d", for=_x"#
+            .parse::<Forwarded>()
+            .expect("could not parse");
+        assert_eq!(
+            forwarded,
+            Forwarded::new(vec![
+                Forward::new(
+                    None,
+                    Some(Interface::Socket(
+                        "[2001:db8:cafe::17]:4711"
+                            .parse::<SocketAddr>()
+                            .expect("???")
+                    )),
+                    Some(String::from("a,b;c=d")),
+                    None,
+                ),
+                Forward::new(
+                    None,
+                    Some(Interface::Obfuscated {
+                        node: String::from("_x"),
+                        port: None
+                    }),
+                    None,
+                    None
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn escaped_host() {
+        let forwarded = r#"host="ex\"ample\\.com""#.parse::<Forwarded>().expect("could not parse");
+        assert_eq!(
+            forwarded.forwards()[0].host(),
+            &Some(String::from(r#"ex"ample\.com"#))
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_rejects() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("Forwarded", r#"for=192.0.2.60, for="[::1]:80";by"#)
+            .extension(Strictness::Strict)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        let rejection = Forwarded::from_request_parts(&mut parts, &())
+            .await
+            .expect_err("should not have parsed");
+        let ForwardedRejection::Invalid(invalid) = rejection else {
+            panic!("wrong rejection {rejection:?}");
+        };
+        assert_eq!(invalid.index(), 1);
+        assert_eq!(invalid.parameter(), "by");
+        assert_eq!(
+            invalid.kind(),
+            ErrorKind::Syntax(SyntaxError::MissingEquals)
+        );
+    }
+
+    #[test]
+    fn lenient_records() {
+        let forwarded = Forwarded::parse(
+            r#"for=192.0.2.60;proto="http, for=x;host=a b"#,
+            Strictness::Lenient,
+        )
+        .expect("lenient parsing should not fail");
+        // The unclosed quote doesn't take the next element with it.
+        assert_eq!(forwarded.forwards().len(), 2);
+        assert_eq!(
+            forwarded.forwards()[0].r#for(),
+            &"192.0.2.60".parse::<Interface>().ok()
+        );
+        assert_eq!(
+            forwarded.forwards()[1].r#for(),
+            &Some(Interface::Identifier(String::from("x")))
+        );
+        assert_eq!(forwarded.invalid().len(), 3);
+        assert_eq!(forwarded.invalid()[0].index(), 0);
+        assert_eq!(forwarded.invalid()[0].parameter(), "proto");
+        assert_eq!(
+            forwarded.invalid()[0].kind(),
+            ErrorKind::Syntax(SyntaxError::UnterminatedQuote)
+        );
+        assert_eq!(forwarded.invalid()[1].index(), 1);
+        assert_eq!(forwarded.invalid()[1].parameter(), "for");
+        assert_eq!(forwarded.invalid()[2].index(), 1);
+        assert_eq!(forwarded.invalid()[2].parameter(), "host");
+
+        let forwarded = Forwarded::parse(r#"for=_a;host=a b,for=_b"#, Strictness::Lenient)
+            .expect("lenient parsing should not fail");
+        assert_eq!(forwarded.forwards().len(), 2);
+        assert_eq!(forwarded.invalid()[0].index(), 0);
+        assert_eq!(forwarded.invalid()[0].parameter(), "host");
+        assert_eq!(
+            forwarded.invalid()[0].kind(),
+            ErrorKind::Syntax(SyntaxError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let forwarded = r#"for=192.0.2.60;Secret="s3cr;t";cdn-id=edge-1"#
+            .parse::<Forwarded>()
+            .expect("could not parse");
+        let forward = &forwarded.forwards()[0];
+        assert_eq!(
+            forward.extensions(),
+            &vec![
+                (String::from("Secret"), String::from("s3cr;t")),
+                (String::from("cdn-id"), String::from("edge-1")),
+            ]
+        );
+        assert_eq!(forward.extension("secret"), Some("s3cr;t"));
+        assert_eq!(forward.extension("CDN-ID"), Some("edge-1"));
+        assert_eq!(forward.extension("missing"), None);
+    }
+
+    #[test]
+    fn duplicates() {
+        let header = "for=192.0.2.60;FOR=192.0.2.61;x=1;X=2";
+        let invalid = Forwarded::parse(header, Strictness::Strict).expect_err("should not parse");
+        assert_eq!(invalid.parameter(), "FOR");
+        assert_eq!(invalid.kind(), ErrorKind::Duplicate);
+
+        let forwarded =
+            Forwarded::parse(header, Strictness::Lenient).expect("lenient parsing should not fail");
+        let forward = &forwarded.forwards()[0];
+        assert_eq!(forward.r#for(), &"192.0.2.60".parse::<Interface>().ok());
+        assert_eq!(forward.extension("x"), Some("1"));
+        assert_eq!(forwarded.invalid().len(), 2);
+        assert_eq!(forwarded.invalid()[1].parameter(), "X");
+    }
+
+    #[test]
+    fn obfuscated() {
+        let parse = |s| Interface::parse(s, Strictness::Strict);
+        assert_eq!(
+            parse("_hidden"),
+            Ok(Interface::Obfuscated {
+                node: String::from("_hidden"),
+                port: None
+            })
+        );
+        assert_eq!(
+            parse(r#""_SEVKISEK:_a.b-c""#),
+            Ok(Interface::Obfuscated {
+                node: String::from("_SEVKISEK"),
+                port: Some(Port::Obfuscated(String::from("_a.b-c")))
+            })
+        );
+        assert_eq!(
+            parse(r#""_x:8080""#),
+            Ok(Interface::Obfuscated {
+                node: String::from("_x"),
+                port: Some(Port::Number(8080))
+            })
+        );
+        assert_eq!(
+            parse(r#""[::1]:_abc""#),
+            Ok(Interface::ObfuscatedPort {
+                ip: "::1".parse::<IpAddr>().expect("???"),
+                port: String::from("_abc")
+            })
+        );
+        assert_eq!(
+            parse(r#""10.0.0.1:_abc""#),
+            Ok(Interface::ObfuscatedPort {
+                ip: "10.0.0.1".parse::<IpAddr>().expect("???"),
+                port: String::from("_abc")
+            })
+        );
+        assert_eq!(parse("unknown"), Ok(Interface::Unknown));
+    }
+
+    #[test]
+    fn invalid_nodes() {
+        for node in [
+            "example.com",
+            "_",
+            "_a b",
+            "2001:db8::1",
+            r#""[10.0.0.1]""#,
+            "10.0.0.1:",
+            "10.0.0.1:080080",
+            "10.0.0.1:_",
+            "[::1]x",
+        ] {
+            assert_eq!(
+                Interface::parse(node, Strictness::Strict),
+                Err(InvalidNode),
+                "{node}"
+            );
+        }
+        assert_eq!(
+            Interface::parse("example.com", Strictness::Lenient),
+            Ok(Interface::Identifier(String::from("example.com")))
+        );
+        assert_eq!(
+            Interface::parse("2001:db8::1", Strictness::Lenient),
+            Ok(Interface::Socket(
+                "[2001:db8::1]:0".parse::<SocketAddr>().expect("???")
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_node_in_header() {
+        let header = r#"for=_hidden, for=example.com;by="[::1]:_p""#;
+        let invalid = Forwarded::parse(header, Strictness::Strict).expect_err("should not parse");
+        assert_eq!(invalid.index(), 1);
+        assert_eq!(invalid.parameter(), "for");
+        assert_eq!(invalid.kind(), ErrorKind::InvalidNode);
+
+        let forwarded =
+            Forwarded::parse(header, Strictness::Lenient).expect("lenient parsing should not fail");
+        assert_eq!(
+            forwarded.forwards()[1].r#for(),
+            &Some(Interface::Identifier(String::from("example.com")))
+        );
+        assert_eq!(forwarded.invalid().len(), 1);
+        assert_eq!(forwarded.invalid()[0].kind(), ErrorKind::InvalidNode);
+    }
+
+    #[test]
+    fn display() {
+        let forwarded = Forwarded::new(vec![
+            Forward::new(
+                Some(Interface::ObfuscatedPort {
+                    ip: "2001:db8::1".parse::<IpAddr>().expect("???"),
+                    port: String::from("_p"),
+                }),
+                Some(Interface::Socket(
+                    "192.0.2.43:0".parse::<SocketAddr>().expect("???"),
+                )),
+                Some(String::from(r#"ex"am,ple"#)),
+                Some(Protocol::Https),
+            )
+            .with_extension("secret", "a b"),
+            Forward::new(
+                None,
+                Some(Interface::Socket(
+                    "[2001:db8:cafe::17]:4711"
+                        .parse::<SocketAddr>()
+                        .expect("???"),
+                )),
+                None,
+                None,
+            ),
+            Forward::new(
+                Some(Interface::Obfuscated {
+                    node: String::from("_hidden"),
+                    port: Some(Port::Obfuscated(String::from("_port"))),
+                }),
+                Some(Interface::Unknown),
+                None,
+                None,
+            ),
+        ]);
+        let header = HeaderValue::try_from(&forwarded).expect("not a header value");
+        assert_eq!(
+            header,
+            r#"by="[2001:db8::1]:_p";for=192.0.2.43;host="ex\"am,ple";proto=https;secret="a b", for="[2001:db8:cafe::17]:4711", by="_hidden:_port";for=unknown"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for header in [
+            r#"for=192.0.2.43, for="[2001:db8:cafe::17]:4711";proto=http"#,
+            r#"by="_a:8080";for=_hidden;host="example.com:8080""#,
+            r#"for="[::1]:_abc";host="a\"b\\c";cdn-id="x,y""#,
+            r#"for=unknown;Secret=s"#,
+        ] {
+            let forwarded = Forwarded::parse(header, Strictness::Strict).expect("could not parse");
+            let written = forwarded.to_string();
+            assert_eq!(written, header);
+            let reparsed = Forwarded::parse(&written, Strictness::Strict).expect("could not parse");
+            assert_eq!(reparsed, forwarded);
+        }
+    }
+
+    #[test]
+    fn invalid_header_value() {
+        let forward = Forward::new(None, None, Some(String::from("a\nb")), None);
+        assert!(HeaderValue::try_from(&forward).is_err());
+    }
+
     #[test]
     fn parse_simple_ipv6() {
         let simple = r#""[::0]:338""#;
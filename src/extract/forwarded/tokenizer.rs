@@ -0,0 +1,289 @@
+//! Splits a `Forwarded` header into elements and `name=value` pairs, as per
+//! [RFC 7239 §4][rfc7239] and the `token`/`quoted-string` rules of [RFC 7230 §3.2.6][rfc7230].
+//!
+//! ```text
+//! Forwarded         = 1#forwarded-element
+//! forwarded-element = [ forwarded-pair ] *( ";" [ forwarded-pair ] )
+//! forwarded-pair    = token "=" value
+//! value             = token / quoted-string
+//! ```
+//!
+//! [rfc7239]: https://www.rfc-editor.org/rfc/rfc7239#section-4
+//! [rfc7230]: https://www.rfc-editor.org/rfc/rfc7230#section-3.2.6
+use crate::Strictness;
+use std::fmt::{self, Write};
+
+/// Something about a pair didn't fit the grammar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxError {
+    /// No `=` between the name and the value.
+    MissingEquals,
+    /// The name isn't a `token`.
+    InvalidName,
+    /// The value is neither a `token`, nor a `quoted-string`.
+    InvalidValue,
+    /// A `quoted-string` without its closing quote.
+    UnterminatedQuote,
+}
+
+/// A pair which didn't fit the grammar, and what we could make out of its name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PairError<'a> {
+    /// The name, or the whole pair if we couldn't find one.
+    pub name: &'a str,
+    pub error: SyntaxError,
+}
+
+/// A single `name=value`, with any quoting undone.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pair<'a> {
+    pub name: &'a str,
+    pub value: String,
+}
+
+/// Optional whitespace, which is allowed around the list separators.
+const OWS: &[char] = &[' ', '\t'];
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_tchar)
+}
+
+/// Senders often leave `ip:port` and `[ipv6]` unquoted, even though neither `:` nor brackets are
+/// allowed in a token.
+fn is_unquoted_value(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| is_tchar(c) || matches!(c, ':' | '[' | ']'))
+}
+
+/// Characters allowed after a `\` in a `quoted-string`.
+fn is_quoted_pair(c: char) -> bool {
+    matches!(c, '\t' | ' ') || c.is_ascii_graphic() || !c.is_ascii()
+}
+
+/// Characters allowed as-is in a `quoted-string`.
+fn is_qdtext(c: char) -> bool {
+    is_quoted_pair(c) && c != '"' && c != '\\'
+}
+
+/// Split on `separator`, skipping over any that are within a `quoted-string`.
+///
+/// A quote which is never closed doesn't quote anything, so it can't swallow the rest of the
+/// header; the pair it's in fails to parse instead.
+fn split_unquoted(s: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut from = 0;
+    loop {
+        // Where the open quote is, if we're in one.
+        let mut quoted = None;
+        let mut escaped = false;
+        for (index, c) in s[from..].char_indices() {
+            let index = from + index;
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' if quoted.is_some() => escaped = true,
+                '"' if quoted.is_some() => quoted = None,
+                '"' => quoted = Some(index),
+                c if c == separator && quoted.is_none() => {
+                    parts.push(&s[start..index]);
+                    start = index + c.len_utf8();
+                }
+                _ => {}
+            }
+        }
+        // Go back and split what came after the quote which was never closed.
+        match quoted {
+            Some(open) => from = open + 1,
+            None => break,
+        }
+    }
+    parts.push(&s[start..]);
+    parts.into_iter()
+}
+
+/// The elements of the header, in order. Empty elements (e.g. `a,,b`) don't count, as per
+/// [RFC 7230 §7](https://www.rfc-editor.org/rfc/rfc7230#section-7), and neither do ones with
+/// only empty pairs (e.g. `a,;,b`).
+pub fn elements(s: &str) -> impl Iterator<Item = &str> {
+    split_unquoted(s, ',')
+        .map(|e| e.trim_matches(OWS))
+        .filter(|e| !e.split(';').all(|p| p.trim_matches(OWS).is_empty()))
+}
+
+/// The pairs within a single element. Empty pairs (e.g. `a=b;;c=d`) are skipped.
+///
+/// When lenient, unquoted values may also contain `:`, `[` and `]`.
+pub fn pairs(
+    element: &str,
+    strictness: Strictness,
+) -> impl Iterator<Item = Result<Pair<'_>, PairError<'_>>> {
+    split_unquoted(element, ';')
+        .map(|p| p.trim_matches(OWS))
+        .filter(|p| !p.is_empty())
+        .map(move |p| pair(p, strictness))
+}
+
+fn pair(raw: &str, strictness: Strictness) -> Result<Pair<'_>, PairError<'_>> {
+    let Some((name, value)) = raw.split_once('=') else {
+        return Err(PairError {
+            name: raw,
+            error: SyntaxError::MissingEquals,
+        });
+    };
+    if !is_token(name) {
+        return Err(PairError {
+            name: raw,
+            error: SyntaxError::InvalidName,
+        });
+    }
+    let error = |error| PairError { name, error };
+    let value = match (value.strip_prefix('"'), strictness) {
+        (Some(quoted), _) => unquote(quoted).map_err(error)?,
+        (None, Strictness::Strict) if is_token(value) => String::from(value),
+        (None, Strictness::Lenient) if is_unquoted_value(value) => String::from(value),
+        (None, _) => return Err(error(SyntaxError::InvalidValue)),
+    };
+    Ok(Pair { name, value })
+}
+
+/// Write `value` as a `token` if it is one, otherwise as a `quoted-string`.
+pub fn write_value(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    if is_token(value) {
+        return f.write_str(value);
+    }
+    f.write_char('"')?;
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char('"')
+}
+
+/// Undo a `quoted-string`, starting just after the opening quote.
+fn unquote(s: &str) -> Result<String, SyntaxError> {
+    let mut value = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    loop {
+        match chars.next() {
+            None => return Err(SyntaxError::UnterminatedQuote),
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some(c) if is_quoted_pair(c) => value.push(c),
+                Some(_) => return Err(SyntaxError::InvalidValue),
+                None => return Err(SyntaxError::UnterminatedQuote),
+            },
+            Some(c) if is_qdtext(c) => value.push(c),
+            Some(_) => return Err(SyntaxError::InvalidValue),
+        }
+    }
+    // Nothing may follow the closing quote.
+    if chars.next().is_some() {
+        return Err(SyntaxError::InvalidValue);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair_of<'a>(name: &'a str, value: &str) -> Result<Pair<'a>, PairError<'a>> {
+        Ok(Pair {
+            name,
+            value: String::from(value),
+        })
+    }
+
+    fn error_of(name: &str, error: SyntaxError) -> Result<Pair<'_>, PairError<'_>> {
+        Err(PairError { name, error })
+    }
+
+    fn pairs(element: &str) -> impl Iterator<Item = Result<Pair<'_>, PairError<'_>>> {
+        super::pairs(element, Strictness::Lenient)
+    }
+
+    #[test]
+    fn quoted_separators() {
+        let header = r#"host="a,b";for="x;y=z", for=_hidden"#;
+        let elements: Vec<&str> = elements(header).collect();
+        assert_eq!(elements, vec![r#"host="a,b";for="x;y=z""#, "for=_hidden"]);
+        let pairs: Vec<_> = pairs(elements[0]).collect();
+        assert_eq!(pairs, vec![pair_of("host", "a,b"), pair_of("for", "x;y=z")]);
+    }
+
+    #[test]
+    fn escapes() {
+        let pairs: Vec<_> = pairs(r#"host="a\"b\\c\,d""#).collect();
+        assert_eq!(pairs, vec![pair_of("host", r#"a"b\c,d"#)]);
+        // An escaped quote doesn't end the string, so the comma is still quoted.
+        let elements: Vec<&str> = elements(r#"host="a\",b", for=c"#).collect();
+        assert_eq!(elements, vec![r#"host="a\",b""#, "for=c"]);
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        // A quote which is never closed doesn't swallow what comes after it.
+        let split: Vec<&str> = elements(r#"for="x, for=b"#).collect();
+        assert_eq!(split, vec![r#"for="x"#, "for=b"]);
+        let split: Vec<&str> = elements(r#"for=a, by="x;host=b, for=c"#).collect();
+        assert_eq!(split, vec!["for=a", r#"by="x;host=b"#, "for=c"]);
+        let pairs: Vec<_> = pairs(r#"by="x;host=b"#).collect();
+        assert_eq!(pairs[1], pair_of("host", "b"));
+    }
+
+    #[test]
+    fn empty_elements_and_pairs() {
+        let elements: Vec<&str> = elements(" ,for=a,, ;for=b , ; ;,").collect();
+        assert_eq!(elements, vec!["for=a", ";for=b"]);
+        let pairs: Vec<_> = pairs(";for=b;").collect();
+        assert_eq!(pairs, vec![pair_of("for", "b")]);
+    }
+
+    #[test]
+    fn errors() {
+        let pair = |raw| pair(raw, Strictness::Strict);
+        assert_eq!(pair("for"), error_of("for", SyntaxError::MissingEquals));
+        assert_eq!(pair("f r=a"), error_of("f r=a", SyntaxError::InvalidName));
+        assert_eq!(pair("=a"), error_of("=a", SyntaxError::InvalidName));
+        assert_eq!(pair("for="), error_of("for", SyntaxError::InvalidValue));
+        assert_eq!(pair("for=a=b"), error_of("for", SyntaxError::InvalidValue));
+        assert_eq!(
+            pair(r#"for="a"b"#),
+            error_of("for", SyntaxError::InvalidValue)
+        );
+        assert_eq!(
+            pair(r#"for="a"#),
+            error_of("for", SyntaxError::UnterminatedQuote)
+        );
+        assert_eq!(
+            pair(r#"for="a\"#),
+            error_of("for", SyntaxError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn unquoted_ports() {
+        let lenient = |raw| pair(raw, Strictness::Lenient);
+        assert_eq!(lenient("for=5.5.5.5:444"), pair_of("for", "5.5.5.5:444"));
+        assert_eq!(lenient("by=[::1]:80"), pair_of("by", "[::1]:80"));
+        let strict = |raw| pair(raw, Strictness::Strict);
+        assert_eq!(
+            strict("for=5.5.5.5:444"),
+            error_of("for", SyntaxError::InvalidValue)
+        );
+        assert_eq!(
+            strict(r#"for="5.5.5.5:444""#),
+            pair_of("for", "5.5.5.5:444")
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! One hop chain, from whichever of `Forwarded` and the `X-Forwarded-*` family was sent.
+use crate::extract::clientip::HopHeader;
+use crate::extract::forwarded::{Forward, Forwarded, ForwardedRejection, Interface, Protocol};
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use crate::extract::xforwardedfor::{XForwardedFor, XForwardedForRejection};
+use crate::extract::xforwardedhost::{XForwardedHost, XForwardedHostRejection};
+use crate::extract::xforwardedport::{XForwardedPort, XForwardedPortRejection};
+use crate::extract::xforwardedproto::{XForwardedProto, XForwardedProtoRejection};
+use axum::body::Body;
+use axum::extract::OptionalFromRequestParts;
+use axum::http::request::Parts;
+use axum::http::uri::Authority;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// A single hop, whichever header it came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hop {
+    by: Option<Interface>,
+    r#for: Option<Interface>,
+    host: Option<String>,
+    proto: Option<Protocol>,
+    port: Option<u16>,
+}
+
+impl Hop {
+    /// Note that the default is an empty struct.
+    pub fn new(
+        by: Option<Interface>,
+        r#for: Option<Interface>,
+        host: Option<String>,
+        proto: Option<Protocol>,
+        port: Option<u16>,
+    ) -> Self {
+        Self {
+            by,
+            r#for,
+            host,
+            proto,
+            port,
+        }
+    }
+
+    /// The forwarder (proxy server). Only `Forwarded` has this.
+    pub fn by(&self) -> &Option<Interface> {
+        &self.by
+    }
+
+    /// The request initiator.
+    pub fn r#for(&self) -> &Option<Interface> {
+        &self.r#for
+    }
+
+    /// `Host` header, as seen by the proxy.
+    pub fn host(&self) -> &Option<String> {
+        &self.host
+    }
+
+    /// The protocol used during this forward.
+    pub fn proto(&self) -> &Option<Protocol> {
+        &self.proto
+    }
+
+    /// The port the request was made to. For `Forwarded`, this is the port in `host`, if any.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl From<&Forward> for Hop {
+    fn from(value: &Forward) -> Self {
+        let port = value
+            .host()
+            .as_ref()
+            .and_then(|host| host.parse::<Authority>().ok())
+            .and_then(|authority| authority.port_u16());
+        Self {
+            by: value.by().clone(),
+            r#for: value.r#for().clone(),
+            host: value.host().clone(),
+            proto: value.proto().clone(),
+            port,
+        }
+    }
+}
+
+/// Which headers [`ForwardedInfo`] reads, in order of preference. The first one that was sent
+/// wins; the others are ignored, not merged in. [`HopHeader::XForwardedFor`] stands for the whole
+/// `X-Forwarded-*` family here.
+///
+/// Put it in the request's extensions, e.g. with `.layer(axum::Extension(precedence))`. By
+/// default, `Forwarded` is preferred over `X-Forwarded-*`. Leave a header out to ignore it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardedPrecedence(pub Vec<HopHeader>);
+
+impl Default for ForwardedPrecedence {
+    fn default() -> Self {
+        Self(vec![HopHeader::Forwarded, HopHeader::XForwardedFor])
+    }
+}
+
+/// The hops from `Forwarded`, or the `X-Forwarded-*` family, as one chain.
+///
+/// `X-Forwarded-For`, `-Proto`, `-Host` and `-Port` are lined up from the right, i.e. the last
+/// of each describe the last hop, the one added by the proxy closest to us. A family member with
+/// fewer entries leaves the earlier hops without that field. So a proxy which appends to
+/// `X-Forwarded-For` but sets the others once (e.g. an AWS ALB) describes the connection it was
+/// sent, even if the client made up some hops of its own.
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::extract::ForwardedInfo;
+///
+/// async fn handler(info: Option<ForwardedInfo>) {
+///     todo!()
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardedInfo {
+    hops: Vec<Hop>,
+    source: HopHeader,
+}
+
+impl ForwardedInfo {
+    /// A new one...
+    pub fn new(hops: Vec<Hop>, source: HopHeader) -> Self {
+        Self { hops, source }
+    }
+
+    /// The hops, client's closest proxy first.
+    pub fn hops(&self) -> &Vec<Hop> {
+        &self.hops
+    }
+
+    /// Which header the hops came from.
+    pub fn source(&self) -> HopHeader {
+        self.source
+    }
+}
+
+/// Why we couldn't extract the hops.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForwardedInfoRejection {
+    /// None of the headers were sent, and they're required.
+    Missing,
+    /// See [`ForwardedRejection`].
+    Forwarded(ForwardedRejection),
+    /// See [`XForwardedForRejection`].
+    XForwardedFor(XForwardedForRejection),
+    /// See [`XForwardedProtoRejection`].
+    XForwardedProto(XForwardedProtoRejection),
+    /// See [`XForwardedHostRejection`].
+    XForwardedHost(XForwardedHostRejection),
+    /// See [`XForwardedPortRejection`].
+    XForwardedPort(XForwardedPortRejection),
+}
+
+impl fmt::Display for ForwardedInfoRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing Forwarded and X-Forwarded-* headers"),
+            Self::Forwarded(rejection) => rejection.fmt(f),
+            Self::XForwardedFor(rejection) => rejection.fmt(f),
+            Self::XForwardedProto(rejection) => rejection.fmt(f),
+            Self::XForwardedHost(rejection) => rejection.fmt(f),
+            Self::XForwardedPort(rejection) => rejection.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ForwardedInfoRejection {}
+
+impl From<ForwardedInfoRejection> for Rejection {
+    fn from(value: ForwardedInfoRejection) -> Self {
+        match value {
+            ForwardedInfoRejection::Missing => {
+                Rejection::new(RejectionKind::Missing, None, value.to_string())
+            }
+            ForwardedInfoRejection::Forwarded(rejection) => Rejection::from(rejection),
+            ForwardedInfoRejection::XForwardedFor(rejection) => Rejection::from(rejection),
+            ForwardedInfoRejection::XForwardedProto(rejection) => Rejection::from(rejection),
+            ForwardedInfoRejection::XForwardedHost(rejection) => Rejection::from(rejection),
+            ForwardedInfoRejection::XForwardedPort(rejection) => Rejection::from(rejection),
+        }
+    }
+}
+
+impl_into_response_via_rejection!(ForwardedInfoRejection);
+
+async fn x_forwarded<S>(
+    parts: &mut Parts,
+    state: &S,
+) -> Result<Option<Vec<Hop>>, ForwardedInfoRejection>
+where
+    S: Send + Sync,
+{
+    let r#for = <XForwardedFor as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+        .await
+        .map_err(ForwardedInfoRejection::XForwardedFor)?;
+    let proto = <XForwardedProto as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+        .await
+        .map_err(ForwardedInfoRejection::XForwardedProto)?;
+    let host = <XForwardedHost as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+        .await
+        .map_err(ForwardedInfoRejection::XForwardedHost)?;
+    let port = <XForwardedPort as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+        .await
+        .map_err(ForwardedInfoRejection::XForwardedPort)?;
+    if r#for.is_none() && proto.is_none() && host.is_none() && port.is_none() {
+        return Ok(None);
+    }
+    let r#for = r#for.map(|h| h.forwards().clone()).unwrap_or_default();
+    let proto = proto.map(|h| h.forwards().clone()).unwrap_or_default();
+    let host = host.map(|h| h.forwards().clone()).unwrap_or_default();
+    let port = port.map(|h| h.forwards().clone()).unwrap_or_default();
+    let len = r#for.len().max(proto.len()).max(host.len()).max(port.len());
+    let hops = (0..len)
+        .map(|i| Hop {
+            by: None,
+            r#for: right_aligned(&r#for, len, i),
+            host: right_aligned(&host, len, i),
+            proto: right_aligned(&proto, len, i),
+            port: right_aligned(&port, len, i).flatten(),
+        })
+        .collect();
+    Ok(Some(hops))
+}
+
+/// The entry in `list` for hop `i` of `len`, lining the lists up from the right.
+pub(crate) fn right_aligned<T: Clone>(list: &[T], len: usize, i: usize) -> Option<T> {
+    (i + list.len())
+        .checked_sub(len)
+        .and_then(|i| list.get(i))
+        .cloned()
+}
+
+/// The hops from just the one header (or family).
+pub(crate) async fn hops<S>(
+    parts: &mut Parts,
+    state: &S,
+    source: HopHeader,
+) -> Result<Option<Vec<Hop>>, ForwardedInfoRejection>
+where
+    S: Send + Sync,
+{
+    match source {
+        HopHeader::Forwarded => Ok(
+            <Forwarded as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                .await
+                .map_err(ForwardedInfoRejection::Forwarded)?
+                .map(|forwarded| forwarded.forwards().iter().map(Hop::from).collect()),
+        ),
+        HopHeader::XForwardedFor => x_forwarded(parts, state).await,
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for ForwardedInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = ForwardedInfoRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let ForwardedPrecedence(precedence) = parts
+            .extensions
+            .get::<ForwardedPrecedence>()
+            .cloned()
+            .unwrap_or_default();
+        for source in precedence {
+            if let Some(hops) = hops(parts, state, source).await? {
+                return Ok(Some(ForwardedInfo::new(hops, source)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl_required_from_request_parts!(ForwardedInfo, ForwardedInfoRejection::Missing);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strictness;
+    use std::net::{IpAddr, SocketAddr};
+
+    async fn extract(
+        headers: &[(&str, &str)],
+        precedence: Option<ForwardedPrecedence>,
+    ) -> Result<Option<ForwardedInfo>, ForwardedInfoRejection> {
+        let mut builder = axum::http::request::Builder::new().method("GET");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        if let Some(precedence) = precedence {
+            builder = builder.extension(precedence);
+        }
+        let (mut parts, _) = builder
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        ForwardedInfo::from_request_parts(&mut parts, &()).await
+    }
+
+    fn ip(ip: &str) -> Option<Interface> {
+        Some(Interface::Socket(SocketAddr::from((
+            ip.parse::<IpAddr>().expect("???"),
+            0,
+        ))))
+    }
+
+    const BOTH: &[(&str, &str)] = &[
+        (
+            "Forwarded",
+            "for=192.0.2.60;by=203.0.113.43;host=example.com:8443;proto=https",
+        ),
+        ("X-Forwarded-For", "198.51.100.1"),
+    ];
+
+    #[tokio::test]
+    async fn prefers_forwarded() {
+        let info = extract(BOTH, None)
+            .await
+            .expect("rejected")
+            .expect("no info");
+        assert_eq!(info.source(), HopHeader::Forwarded);
+        assert_eq!(
+            info.hops(),
+            &vec![Hop::new(
+                ip("203.0.113.43"),
+                ip("192.0.2.60"),
+                Some(String::from("example.com:8443")),
+                Some(Protocol::Https),
+                Some(8443),
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn precedence() {
+        let precedence = ForwardedPrecedence(vec![HopHeader::XForwardedFor, HopHeader::Forwarded]);
+        let info = extract(BOTH, Some(precedence))
+            .await
+            .expect("rejected")
+            .expect("no info");
+        assert_eq!(info.source(), HopHeader::XForwardedFor);
+        assert_eq!(
+            info.hops(),
+            &vec![Hop::new(None, ip("198.51.100.1"), None, None, None)]
+        );
+
+        let only = ForwardedPrecedence(vec![HopHeader::XForwardedFor]);
+        let headers = &BOTH[..1];
+        assert_eq!(extract(headers, Some(only)).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_x_forwarded() {
+        let info = extract(
+            &[
+                ("X-Forwarded-For", "192.0.2.60, 198.51.100.1"),
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "example.com"),
+                ("X-Forwarded-Port", "443"),
+            ],
+            None,
+        )
+        .await
+        .expect("rejected")
+        .expect("no info");
+        assert_eq!(info.source(), HopHeader::XForwardedFor);
+        assert_eq!(
+            info.hops(),
+            &vec![
+                Hop::new(None, ip("192.0.2.60"), None, None, None),
+                Hop::new(
+                    None,
+                    ip("198.51.100.1"),
+                    Some(String::from("example.com")),
+                    Some(Protocol::Https),
+                    Some(443),
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn client_prepended_x_forwarded_for() {
+        // The client sent `X-Forwarded-For: 203.0.113.9`, and the proxy appended its peer and set
+        // the rest once.
+        let info = extract(
+            &[
+                ("X-Forwarded-For", "203.0.113.9, 192.0.2.1"),
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "example.com"),
+            ],
+            None,
+        )
+        .await
+        .expect("rejected")
+        .expect("no info");
+        assert_eq!(
+            info.hops(),
+            &vec![
+                Hop::new(None, ip("203.0.113.9"), None, None, None),
+                Hop::new(
+                    None,
+                    ip("192.0.2.1"),
+                    Some(String::from("example.com")),
+                    Some(Protocol::Https),
+                    None,
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn without_x_forwarded_for() {
+        let info = extract(&[("X-Forwarded-Proto", "https")], None)
+            .await
+            .expect("rejected")
+            .expect("no info");
+        assert_eq!(
+            info.hops(),
+            &vec![Hop::new(None, None, None, Some(Protocol::Https), None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing() {
+        assert_eq!(extract(&[("Host", "example.com")], None).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn invalid_entries() {
+        let info = extract(&[("X-Forwarded-Port", "https, 443")], None)
+            .await
+            .expect("rejected")
+            .expect("no info");
+        assert_eq!(
+            info.hops(),
+            &vec![
+                Hop::new(None, None, None, None, None),
+                Hop::new(None, None, None, None, Some(443)),
+            ]
+        );
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .header("X-Forwarded-Port", "https, 443")
+            .extension(Strictness::Strict)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert!(matches!(
+            ForwardedInfo::from_request_parts(&mut parts, &()).await,
+            Err(ForwardedInfoRejection::XForwardedPort(_))
+        ));
+    }
+}
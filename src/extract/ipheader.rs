@@ -0,0 +1,342 @@
+//! Support for the single-value client IP headers CDNs and platforms set, e.g. `X-Real-IP` and
+//! `CF-Connecting-IP`.
+//!
+//! These are only as trustworthy as whatever is in front of us: anyone can send them, so make
+//! sure the edge overwrites them (or use [`ClientIp`](crate::extract::ClientIp)).
+use crate::Strictness;
+use crate::extract::forwarded::Interface;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use axum::body::Body;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use axum::http::header::HeaderName;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Why we couldn't extract a client IP header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IpHeaderRejection {
+    /// There wasn't one, and it's required.
+    Missing(HeaderName),
+    /// The header's gotta be at least UTF-8.
+    NotAString(HeaderName),
+    /// It was sent more than once, so we can't tell which to believe.
+    Duplicate(HeaderName),
+    /// The value isn't an IP, with or without a port.
+    Invalid(HeaderName),
+    /// [`IpHeader`] was used without an [`IpHeaderName`] in the request's extensions.
+    MissingName,
+}
+
+impl fmt::Display for IpHeaderRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "missing {name} header"),
+            Self::NotAString(name) => write!(f, "could not parse {name} header into string"),
+            Self::Duplicate(name) => write!(f, "{name} header was sent more than once"),
+            Self::Invalid(name) => write!(f, "could not parse IP in {name} header"),
+            Self::MissingName => f.write_str("missing IpHeaderName extension"),
+        }
+    }
+}
+
+impl std::error::Error for IpHeaderRejection {}
+
+impl From<IpHeaderRejection> for Rejection {
+    fn from(value: IpHeaderRejection) -> Self {
+        let message = value.to_string();
+        let (kind, header) = match value {
+            IpHeaderRejection::Missing(name) => (RejectionKind::Missing, Some(name)),
+            IpHeaderRejection::NotAString(name) => (RejectionKind::NotAString, Some(name)),
+            IpHeaderRejection::Duplicate(name) => (RejectionKind::Duplicate, Some(name)),
+            IpHeaderRejection::Invalid(name) => (RejectionKind::Invalid, Some(name)),
+            IpHeaderRejection::MissingName => (RejectionKind::Misconfigured, None),
+        };
+        Rejection::new(kind, header, message)
+    }
+}
+
+impl_into_response_via_rejection!(IpHeaderRejection);
+
+/// Parse the header `name` like a `Forwarded` node: an IPv4 or IPv6 address with an optional
+/// port, which is `0` if absent. Bare IPv6 addresses are only accepted when lenient.
+fn parse(parts: &Parts, name: &HeaderName) -> Result<Option<SocketAddr>, IpHeaderRejection> {
+    let mut values = parts.headers.get_all(name).iter();
+    let Some(header_raw) = values.next() else {
+        return Ok(None);
+    };
+    if values.next().is_some() {
+        return Err(IpHeaderRejection::Duplicate(name.clone()));
+    }
+    let Ok(header_str) = header_raw.to_str() else {
+        return Err(IpHeaderRejection::NotAString(name.clone()));
+    };
+    let strictness = parts
+        .extensions
+        .get::<Strictness>()
+        .copied()
+        .unwrap_or(Strictness::Lenient);
+    match Interface::parse(header_str, strictness) {
+        Ok(Interface::Socket(socket)) => Ok(Some(socket)),
+        _ => Err(IpHeaderRejection::Invalid(name.clone())),
+    }
+}
+
+macro_rules! ip_header {
+    ($($ty:ident => $name:literal, $header:literal, $who:literal;)+) => {
+        $(
+            #[doc = concat!("Get the IP from the `", $header, "` header, as set by ", $who, ".")]
+            ///
+            /// Example:
+            ///
+            /// ```rust
+            #[doc = concat!("use axum_proxied::extract::", stringify!($ty), ";")]
+            ///
+            #[doc = concat!("async fn handler(ip: Option<", stringify!($ty), ">) {")]
+            ///     todo!()
+            /// }
+            /// ```
+            #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+            pub struct $ty {
+                addr: SocketAddr,
+            }
+
+            impl $ty {
+                /// The name of the header.
+                pub const HEADER: HeaderName = HeaderName::from_static($name);
+
+                /// A new one...
+                pub fn new(addr: SocketAddr) -> Self {
+                    Self { addr }
+                }
+
+                /// The IP and port. The port is `0` if there wasn't one.
+                pub fn addr(&self) -> SocketAddr {
+                    self.addr
+                }
+
+                /// The IP.
+                pub fn ip(&self) -> IpAddr {
+                    self.addr.ip()
+                }
+            }
+
+            impl<S> OptionalFromRequestParts<S> for $ty
+            where
+                S: Send + Sync,
+            {
+                type Rejection = IpHeaderRejection;
+
+                async fn from_request_parts(
+                    parts: &mut Parts,
+                    _state: &S,
+                ) -> Result<Option<Self>, Self::Rejection> {
+                    Ok(parse(parts, &Self::HEADER)?.map(Self::new))
+                }
+            }
+
+            impl_required_from_request_parts!($ty, IpHeaderRejection::Missing($ty::HEADER));
+        )+
+    };
+}
+
+ip_header! {
+    XRealIp => "x-real-ip", "X-Real-IP", "nginx and others";
+    CfConnectingIp => "cf-connecting-ip", "CF-Connecting-IP", "Cloudflare";
+    TrueClientIp => "true-client-ip", "True-Client-IP", "Akamai and Cloudflare Enterprise";
+    FlyClientIp => "fly-client-ip", "Fly-Client-IP", "Fly.io";
+    FastlyClientIp => "fastly-client-ip", "Fastly-Client-IP", "Fastly";
+}
+
+/// Which header [`IpHeader`] reads. Put it in the request's extensions, e.g. with
+/// `.layer(axum::Extension(IpHeaderName(name)))`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpHeaderName(pub HeaderName);
+
+/// Get the IP from whichever header is named by the [`IpHeaderName`] in the request's
+/// extensions, for platforms without their own extractor.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Extension, Router, http::HeaderName, routing::get};
+/// use axum_proxied::extract::{IpHeader, IpHeaderName};
+///
+/// async fn handler(ip: Option<IpHeader>) {
+///     todo!()
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(IpHeaderName(HeaderName::from_static("x-client-ip"))));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpHeader {
+    addr: SocketAddr,
+}
+
+impl IpHeader {
+    /// A new one...
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    /// The IP and port. The port is `0` if there wasn't one.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The IP.
+    pub fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for IpHeader
+where
+    S: Send + Sync,
+{
+    type Rejection = IpHeaderRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let Some(IpHeaderName(name)) = parts.extensions.get::<IpHeaderName>() else {
+            return Err(IpHeaderRejection::MissingName);
+        };
+        Ok(parse(parts, name)?.map(Self::new))
+    }
+}
+
+/// Rejects if the header wasn't sent; use an `Option` if it's optional.
+impl<S> FromRequestParts<S> for IpHeader
+where
+    S: Send + Sync,
+{
+    type Rejection = IpHeaderRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Self as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or_else(|| match parts.extensions.get::<IpHeaderName>() {
+                Some(IpHeaderName(name)) => IpHeaderRejection::Missing(name.clone()),
+                None => IpHeaderRejection::MissingName,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(headers: &[(&str, &str)]) -> Parts {
+        let mut builder = axum::http::request::Builder::new().method("GET");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder
+            .body(())
+            .expect("could not build request")
+            .into_parts()
+            .0
+    }
+
+    async fn optional<T>(parts: &mut Parts) -> Result<Option<T>, IpHeaderRejection>
+    where
+        T: OptionalFromRequestParts<(), Rejection = IpHeaderRejection>,
+    {
+        T::from_request_parts(parts, &()).await
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse::<SocketAddr>().expect("???")
+    }
+
+    #[tokio::test]
+    async fn forms() {
+        for (value, expected) in [
+            ("192.0.2.1", "192.0.2.1:0"),
+            (" 192.0.2.1:4711 ", "192.0.2.1:4711"),
+            ("2001:db8::1", "[2001:db8::1]:0"),
+            ("[2001:db8::1]", "[2001:db8::1]:0"),
+            ("[2001:db8::1]:4711", "[2001:db8::1]:4711"),
+        ] {
+            let mut parts = parts(&[("CF-Connecting-IP", value)]);
+            assert_eq!(
+                optional::<CfConnectingIp>(&mut parts).await,
+                Ok(Some(CfConnectingIp::new(addr(expected)))),
+                "{value}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_wants_brackets() {
+        let mut parts = parts(&[("X-Real-IP", "2001:db8::1")]);
+        parts.extensions.insert(Strictness::Strict);
+        assert_eq!(
+            optional::<XRealIp>(&mut parts).await,
+            Err(IpHeaderRejection::Invalid(XRealIp::HEADER))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        for value in [
+            "unknown",
+            "_hidden",
+            "example.com",
+            "192.0.2.1, 192.0.2.2",
+            "",
+        ] {
+            let mut parts = parts(&[("True-Client-IP", value)]);
+            assert_eq!(
+                optional::<TrueClientIp>(&mut parts).await,
+                Err(IpHeaderRejection::Invalid(TrueClientIp::HEADER)),
+                "{value}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate() {
+        let mut parts = parts(&[
+            ("Fly-Client-IP", "192.0.2.1"),
+            ("Fly-Client-IP", "192.0.2.2"),
+        ]);
+        assert_eq!(
+            optional::<FlyClientIp>(&mut parts).await,
+            Err(IpHeaderRejection::Duplicate(FlyClientIp::HEADER))
+        );
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        let mut parts = parts(&[("X-Real-IP", "192.0.2.1")]);
+        assert_eq!(optional::<FastlyClientIp>(&mut parts).await, Ok(None));
+        assert_eq!(
+            <FastlyClientIp as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await,
+            Err(IpHeaderRejection::Missing(FastlyClientIp::HEADER))
+        );
+    }
+
+    #[tokio::test]
+    async fn generic() {
+        let mut parts = parts(&[("X-Client-IP", "192.0.2.1")]);
+        assert_eq!(
+            optional::<IpHeader>(&mut parts).await,
+            Err(IpHeaderRejection::MissingName)
+        );
+        parts
+            .extensions
+            .insert(IpHeaderName(HeaderName::from_static("x-client-ip")));
+        assert_eq!(
+            optional::<IpHeader>(&mut parts).await,
+            Ok(Some(IpHeader::new(addr("192.0.2.1:0"))))
+        );
+    }
+}
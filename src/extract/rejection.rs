@@ -0,0 +1,165 @@
+//! What every extractor's rejection boils down to, so they can all be answered the same way.
+//!
+//! The default response is the [`Rejection::status`] and the message as plain text. The
+//! [`Rejection`] is also left in the response's extensions, so
+//! [`RejectionResponseLayer`](crate::middleware::RejectionResponseLayer) (or anything else) can
+//! swap in a different response, e.g. JSON problem details.
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::http::header::HeaderName;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// What sort of thing went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RejectionKind {
+    /// The header is required, but wasn't sent.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// The header was sent more than once, but only makes sense once.
+    Duplicate,
+    /// The header didn't parse.
+    Invalid,
+    /// The header parsed, but isn't something we'll accept, e.g. a host we don't serve.
+    NotAllowed,
+    /// The server wasn't set up for the extractor, e.g. without connect info. Not the client's
+    /// fault.
+    Misconfigured,
+}
+
+impl RejectionKind {
+    /// `500 Internal Server Error` if it's [`RejectionKind::Misconfigured`], otherwise
+    /// `400 Bad Request`.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::Misconfigured => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Missing
+            | Self::NotAString
+            | Self::Duplicate
+            | Self::Invalid
+            | Self::NotAllowed => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Any of this crate's extractor rejections, boiled down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    kind: RejectionKind,
+    header: Option<HeaderName>,
+    message: String,
+}
+
+impl Rejection {
+    /// A new one...
+    pub fn new(
+        kind: RejectionKind,
+        header: Option<HeaderName>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            kind,
+            header,
+            message: message.into(),
+        }
+    }
+
+    /// What sort of thing went wrong.
+    pub fn kind(&self) -> RejectionKind {
+        self.kind
+    }
+
+    /// The header at fault, if it was down to one.
+    pub fn header(&self) -> Option<&HeaderName> {
+        self.header.as_ref()
+    }
+
+    /// A description for humans.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// See [`RejectionKind::status`].
+    pub fn status(&self) -> StatusCode {
+        self.kind.status()
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response<Body> {
+        let mut response = (self.status(), self.message.clone()).into_response();
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Implement [`IntoResponse`] for a rejection, via [`Rejection`].
+macro_rules! impl_into_response_via_rejection {
+    ($($ty:ty),+) => {
+        $(
+            impl IntoResponse for $ty {
+                fn into_response(self) -> Response<Body> {
+                    $crate::extract::rejection::Rejection::from(self).into_response()
+                }
+            }
+        )+
+    };
+}
+
+/// Implement [`FromRequestParts`](axum::extract::FromRequestParts) for an extractor which
+/// already implements [`OptionalFromRequestParts`](axum::extract::OptionalFromRequestParts),
+/// rejecting with `$missing` if the header wasn't sent.
+macro_rules! impl_required_from_request_parts {
+    ($ty:ty, $missing:expr) => {
+        /// Rejects if the header wasn't sent; use an `Option` if it's optional.
+        impl<S> axum::extract::FromRequestParts<S> for $ty
+        where
+            S: Send + Sync,
+        {
+            type Rejection = <$ty as axum::extract::OptionalFromRequestParts<S>>::Rejection;
+
+            async fn from_request_parts(
+                parts: &mut Parts,
+                state: &S,
+            ) -> Result<Self, Self::Rejection> {
+                <$ty as axum::extract::OptionalFromRequestParts<S>>::from_request_parts(
+                    parts, state,
+                )
+                .await?
+                .ok_or_else(|| $missing)
+            }
+        }
+    };
+}
+
+pub(crate) use {impl_into_response_via_rejection, impl_required_from_request_parts};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response() {
+        let rejection = Rejection::new(
+            RejectionKind::Invalid,
+            Some(HeaderName::from_static("x-forwarded-for")),
+            "nope",
+        );
+        let response = rejection.clone().into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.extensions().get::<Rejection>(), Some(&rejection));
+        assert_eq!(
+            Rejection::new(RejectionKind::Misconfigured, None, "oops").status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -1,15 +1,31 @@
 //! Support for HTTP Header [`X-Forwarded-For`][mdn].
 //!
 //! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/X-Forwarded-For
+use crate::Strictness;
+use crate::extract::forwarded::Interface;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use crate::extract::{list_header, parse_list};
 use axum::body::Body;
 use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
 use axum::http::request::Parts;
-use axum::http::{StatusCode, header};
 use axum::response::{IntoResponse, Response};
-use std::net::IpAddr;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
 
 /// Get the contents of the `X-Forwarded-For` header.
 ///
+/// Entries are parsed like a `Forwarded` node, so `192.0.2.43`, `192.0.2.43:4711`,
+/// `2001:db8::1`, `[2001:db8::1]:443`, `unknown` and `_hidden` are all fine. Empty entries are
+/// skipped.
+///
+/// Anything else is rejected if there's a [`Strictness::Strict`] in the request's extensions.
+/// Otherwise it's kept as an [`Interface::Identifier`], so one broken proxy doesn't take down the
+/// whole request; see [`XForwardedFor::invalid`].
+///
 /// Example:
 ///
 /// ```rust
@@ -21,29 +37,122 @@ use std::net::IpAddr;
 /// ```
 #[derive(Debug, PartialEq, Eq)]
 pub struct XForwardedFor {
-    forwards: Vec<IpAddr>,
+    forwards: Vec<Interface>,
 }
 
-/// The header's gotta be at least UTF-8.
+/// Why we couldn't extract the `X-Forwarded-For` header.
 #[derive(Debug, PartialEq, Eq)]
-pub struct XForwardedForRejection(&'static str);
+pub enum XForwardedForRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// An entry didn't parse, and we're being strict about it.
+    Invalid(InvalidEntry),
+}
+
+impl fmt::Display for XForwardedForRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing X-Forwarded-For header"),
+            Self::NotAString => f.write_str("could not parse X-Forwarded-For header into string"),
+            Self::Invalid(invalid) => invalid.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for XForwardedForRejection {}
+
+impl From<XForwardedForRejection> for Rejection {
+    fn from(value: XForwardedForRejection) -> Self {
+        let kind = match value {
+            XForwardedForRejection::Missing => RejectionKind::Missing,
+            XForwardedForRejection::NotAString => RejectionKind::NotAString,
+            XForwardedForRejection::Invalid(_) => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(X_FORWARDED_FOR_HEADER), value.to_string())
+    }
+}
 
-impl IntoResponse for XForwardedForRejection {
-    fn into_response(self) -> Response<Body> {
-        (StatusCode::BAD_REQUEST, self.0).into_response()
+impl_into_response_via_rejection!(XForwardedForRejection);
+
+/// An entry which isn't an IP, `unknown`, nor an obfuscated name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidEntry {
+    index: usize,
+    entry: String,
+}
+
+impl InvalidEntry {
+    /// Which entry, counting from `0` and skipping empty ones.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The entry, as sent.
+    pub fn entry(&self) -> &str {
+        &self.entry
     }
 }
 
+impl fmt::Display for InvalidEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "X-Forwarded-For entry {}, `{}`: not an IP",
+            self.index, self.entry
+        )
+    }
+}
+
+impl std::error::Error for InvalidEntry {}
+
 impl XForwardedFor {
     /// A new one...
-    pub fn new(forwards: Vec<std::net::IpAddr>) -> Self {
+    pub fn new(forwards: Vec<Interface>) -> Self {
         Self { forwards }
     }
 
-    /// A list of [`IpAddr`]s.
-    pub fn forwards(&self) -> &Vec<IpAddr> {
+    /// A list of [`Interface`]s, one per entry.
+    pub fn forwards(&self) -> &Vec<Interface> {
         &self.forwards
     }
+
+    /// The entries we couldn't make sense of, with their index in [`XForwardedFor::forwards`].
+    pub fn invalid(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.forwards
+            .iter()
+            .enumerate()
+            .filter_map(|(index, forward)| match forward {
+                Interface::Identifier(entry) => Some((index, entry.as_str())),
+                _ => None,
+            })
+    }
+
+    /// Parse the header's value.
+    ///
+    /// When strict, the first invalid entry is an error. When lenient, it's kept as an
+    /// [`Interface::Identifier`].
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, InvalidEntry> {
+        let forwards = parse_list(s, strictness, |entry| match entry.parse::<Interface>() {
+            Ok(Interface::Identifier(entry)) => Err(Interface::Identifier(entry)),
+            Ok(forward) => Ok(forward),
+        })
+        .map_err(|(index, entry)| InvalidEntry {
+            index,
+            entry: String::from(entry),
+        })?;
+        Ok(Self::new(forwards))
+    }
+}
+
+/// Parses leniently, see [`XForwardedFor::parse`].
+impl FromStr for XForwardedFor {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s, Strictness::Lenient).unwrap_or_else(|_| Self::new(vec![])))
+    }
 }
 
 const X_FORWARDED_FOR_HEADER: header::HeaderName =
@@ -59,29 +168,32 @@ where
         parts: &mut Parts,
         _state: &S,
     ) -> Result<Option<Self>, Self::Rejection> {
-        let Some(header_raw) = parts.headers.get(X_FORWARDED_FOR_HEADER) else {
-            return Ok(None);
+        let header_str = match list_header(&parts.headers, X_FORWARDED_FOR_HEADER) {
+            Ok(Some(header_str)) => header_str,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(XForwardedForRejection::NotAString),
         };
-        let Ok(header_str) = header_raw.to_str() else {
-            return Err(XForwardedForRejection("could not parse header into string"));
-        };
-        let mut forwards = vec![];
-        let ips_raw = header_str.split(',');
-        for ip_raw in ips_raw {
-            let Ok(ip) = ip_raw.trim().parse::<std::net::IpAddr>() else {
-                return Err(XForwardedForRejection(
-                    "could not parse IP in HTTP Header X-Forwarded-For (axum-stuff)",
-                ));
-            };
-            forwards.push(ip);
-        }
-        Ok(Some(XForwardedFor::new(forwards)))
+        let strictness = parts
+            .extensions
+            .get::<Strictness>()
+            .copied()
+            .unwrap_or(Strictness::Lenient);
+        XForwardedFor::parse(&header_str, strictness)
+            .map(Some)
+            .map_err(XForwardedForRejection::Invalid)
     }
 }
 
+impl_required_from_request_parts!(XForwardedFor, XForwardedForRejection::Missing);
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::{IpAddr, SocketAddr};
+
+    fn ip(ip: &str) -> Interface {
+        Interface::Socket(SocketAddr::from((ip.parse::<IpAddr>().expect("???"), 0)))
+    }
 
     /// From
     /// [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Forwarded).
@@ -100,11 +212,98 @@ mod tests {
         assert_eq!(
             xforwarded,
             XForwardedFor {
-                forwards: vec![
-                    "192.0.2.43".parse::<IpAddr>().expect("???"),
-                    "2001:db8:cafe::17".parse::<IpAddr>().expect("???"),
-                ]
+                forwards: vec![ip("192.0.2.43"), ip("2001:db8:cafe::17"),]
             }
         );
     }
+
+    #[tokio::test]
+    async fn split_headers() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-For", "192.0.2.43")
+            .header("X-Forwarded-For", "198.51.100.1, 2001:db8:cafe::17")
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        let xforwarded = XForwardedFor::from_request_parts(&mut parts, &())
+            .await
+            .expect("could not parse HTTP headers")
+            .expect("could not parse X-Forwarded-For header");
+        assert_eq!(
+            xforwarded.forwards(),
+            &vec![
+                ip("192.0.2.43"),
+                ip("198.51.100.1"),
+                ip("2001:db8:cafe::17"),
+            ]
+        );
+    }
+
+    #[test]
+    fn forms() {
+        let xforwarded: XForwardedFor =
+            "192.0.2.43:4711, [2001:db8::1]:443, unknown, , _hidden, [2001:db8::2]"
+                .parse()
+                .expect("could not parse");
+        assert_eq!(
+            xforwarded.forwards(),
+            &vec![
+                Interface::Socket("192.0.2.43:4711".parse::<SocketAddr>().expect("???")),
+                Interface::Socket("[2001:db8::1]:443".parse::<SocketAddr>().expect("???")),
+                Interface::Unknown,
+                Interface::Obfuscated {
+                    node: String::from("_hidden"),
+                    port: None
+                },
+                ip("2001:db8::2"),
+            ]
+        );
+        assert_eq!(xforwarded.invalid().count(), 0);
+    }
+
+    #[test]
+    fn lenient_marks_invalid() {
+        let xforwarded =
+            XForwardedFor::parse("192.0.2.43, garbage, 198.51.100.1", Strictness::Lenient)
+                .expect("lenient parsing failed");
+        assert_eq!(
+            xforwarded.forwards(),
+            &vec![
+                ip("192.0.2.43"),
+                Interface::Identifier(String::from("garbage")),
+                ip("198.51.100.1"),
+            ]
+        );
+        assert_eq!(
+            xforwarded.invalid().collect::<Vec<_>>(),
+            vec![(1, "garbage")]
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_rejects() {
+        assert_eq!(
+            XForwardedFor::parse("192.0.2.43, , 300.0.0.1", Strictness::Strict),
+            Err(InvalidEntry {
+                index: 1,
+                entry: String::from("300.0.0.1")
+            })
+        );
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-For", "192.0.2.43, garbage")
+            .extension(Strictness::Strict)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            <XForwardedFor as OptionalFromRequestParts<()>>::from_request_parts(&mut parts, &())
+                .await,
+            Err(XForwardedForRejection::Invalid(InvalidEntry {
+                index: 1,
+                entry: String::from("garbage")
+            }))
+        );
+    }
 }
@@ -0,0 +1,212 @@
+//! Support for HTTP Header [`X-Forwarded-Host`][mdn].
+//!
+//! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/X-Forwarded-Host
+use crate::Strictness;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use crate::extract::{list_header, parse_list};
+use axum::body::Body;
+use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::http::uri::Authority;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// Get the contents of the `X-Forwarded-Host` header.
+///
+/// Each proxy which added to it is a hop, with the client's closest proxy first, like
+/// `X-Forwarded-For`. Hosts are as sent, e.g. `example.com` or `example.com:8080`. Empty entries
+/// are skipped.
+///
+/// An entry which isn't a `host[:port]` is rejected if there's a [`Strictness::Strict`] in the
+/// request's extensions. Otherwise it's kept as sent; see [`XForwardedHost::invalid`].
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::extract::XForwardedHost;
+///
+/// async fn handler(xforwarded: Option<XForwardedHost>) {
+///     todo!()
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct XForwardedHost {
+    forwards: Vec<String>,
+}
+
+/// Why we couldn't extract the `X-Forwarded-Host` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum XForwardedHostRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// This entry (counting from `0`, skipping empty ones) isn't a `host[:port]`, and we're
+    /// being strict about it.
+    Invalid(usize, String),
+}
+
+impl fmt::Display for XForwardedHostRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing X-Forwarded-Host header"),
+            Self::NotAString => f.write_str("could not parse X-Forwarded-Host header into string"),
+            Self::Invalid(index, entry) => {
+                write!(f, "X-Forwarded-Host entry {index}, `{entry}`: not a host")
+            }
+        }
+    }
+}
+
+impl std::error::Error for XForwardedHostRejection {}
+
+impl From<XForwardedHostRejection> for Rejection {
+    fn from(value: XForwardedHostRejection) -> Self {
+        let kind = match value {
+            XForwardedHostRejection::Missing => RejectionKind::Missing,
+            XForwardedHostRejection::NotAString => RejectionKind::NotAString,
+            XForwardedHostRejection::Invalid(..) => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(X_FORWARDED_HOST_HEADER), value.to_string())
+    }
+}
+
+impl_into_response_via_rejection!(XForwardedHostRejection);
+
+impl XForwardedHost {
+    /// A new one...
+    pub fn new(forwards: Vec<String>) -> Self {
+        Self { forwards }
+    }
+
+    /// A list of hosts.
+    pub fn forwards(&self) -> &Vec<String> {
+        &self.forwards
+    }
+
+    /// The entries which weren't a `host[:port]`, with their index in
+    /// [`XForwardedHost::forwards`].
+    pub fn invalid(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.forwards
+            .iter()
+            .enumerate()
+            .filter(|(_, host)| !is_host(host))
+            .map(|(index, host)| (index, host.as_str()))
+    }
+
+    /// Parse the header's value.
+    ///
+    /// When strict, the first invalid entry is an error. When lenient, it's kept as sent.
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, XForwardedHostRejection> {
+        let forwards = parse_list(s, strictness, |entry| {
+            let host = String::from(entry);
+            if is_host(entry) { Ok(host) } else { Err(host) }
+        })
+        .map_err(|(index, entry)| XForwardedHostRejection::Invalid(index, String::from(entry)))?;
+        Ok(Self::new(forwards))
+    }
+}
+
+const X_FORWARDED_HOST_HEADER: header::HeaderName =
+    header::HeaderName::from_static("x-forwarded-host");
+
+/// Whether `host` is what could go in a `Host` header, i.e. an authority without userinfo.
+fn is_host(host: &str) -> bool {
+    !host.contains('@') && host.parse::<Authority>().is_ok()
+}
+
+impl<S> OptionalFromRequestParts<S> for XForwardedHost
+where
+    S: Send + Sync,
+{
+    type Rejection = XForwardedHostRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let header_str = match list_header(&parts.headers, X_FORWARDED_HOST_HEADER) {
+            Ok(Some(header_str)) => header_str,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(XForwardedHostRejection::NotAString),
+        };
+        let strictness = parts
+            .extensions
+            .get::<Strictness>()
+            .copied()
+            .unwrap_or(Strictness::Lenient);
+        XForwardedHost::parse(&header_str, strictness).map(Some)
+    }
+}
+
+impl_required_from_request_parts!(XForwardedHost, XForwardedHostRejection::Missing);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(header: &str) -> Result<Option<XForwardedHost>, XForwardedHostRejection> {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Host", header)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        XForwardedHost::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn simple() {
+        assert_eq!(
+            parse("example.com, internal:8080 ,[2001:db8::1]:443").await,
+            Ok(Some(XForwardedHost {
+                forwards: vec![
+                    String::from("example.com"),
+                    String::from("internal:8080"),
+                    String::from("[2001:db8::1]:443"),
+                ]
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_and_invalid() {
+        let hosts = parse(",example.com,, user@example.com, other")
+            .await
+            .expect("lenient parsing should not fail")
+            .expect("no X-Forwarded-Host");
+        assert_eq!(hosts.forwards().len(), 3);
+        assert_eq!(
+            hosts.invalid().collect::<Vec<_>>(),
+            vec![(1, "user@example.com")]
+        );
+        for invalid in ["user@example.com", "example.com/path", "exa mple.com"] {
+            assert_eq!(
+                XForwardedHost::parse(invalid, Strictness::Strict),
+                Err(XForwardedHostRejection::Invalid(0, String::from(invalid)))
+            );
+        }
+        assert_eq!(
+            XForwardedHost::parse("example.com,,other", Strictness::Strict)
+                .map(|hosts| hosts.forwards().len()),
+            Ok(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn not_a_string() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Host", &b"example.com\xff"[..])
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            XForwardedHost::from_request_parts(&mut parts, &()).await,
+            Err(XForwardedHostRejection::NotAString)
+        );
+    }
+}
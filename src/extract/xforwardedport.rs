@@ -0,0 +1,197 @@
+//! Support for HTTP Header `X-Forwarded-Port`, the port the client connected to.
+use crate::Strictness;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use crate::extract::{list_header, parse_list};
+use axum::body::Body;
+use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// Get the contents of the `X-Forwarded-Port` header.
+///
+/// Each proxy which added to it is a hop, with the client's closest proxy first, like
+/// `X-Forwarded-For`. Empty entries are skipped.
+///
+/// An entry which isn't a port is rejected if there's a [`Strictness::Strict`] in the request's
+/// extensions. Otherwise it's kept as a `None`; see [`XForwardedPort::invalid`].
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::extract::XForwardedPort;
+///
+/// async fn handler(xforwarded: Option<XForwardedPort>) {
+///     todo!()
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct XForwardedPort {
+    forwards: Vec<Option<u16>>,
+}
+
+/// Why we couldn't extract the `X-Forwarded-Port` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum XForwardedPortRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// This entry (counting from `0`, skipping empty ones) isn't a port, and we're being
+    /// strict about it.
+    Invalid(usize, String),
+}
+
+impl fmt::Display for XForwardedPortRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing X-Forwarded-Port header"),
+            Self::NotAString => f.write_str("could not parse X-Forwarded-Port header into string"),
+            Self::Invalid(index, entry) => {
+                write!(f, "X-Forwarded-Port entry {index}, `{entry}`: not a port")
+            }
+        }
+    }
+}
+
+impl std::error::Error for XForwardedPortRejection {}
+
+impl From<XForwardedPortRejection> for Rejection {
+    fn from(value: XForwardedPortRejection) -> Self {
+        let kind = match value {
+            XForwardedPortRejection::Missing => RejectionKind::Missing,
+            XForwardedPortRejection::NotAString => RejectionKind::NotAString,
+            XForwardedPortRejection::Invalid(..) => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(X_FORWARDED_PORT_HEADER), value.to_string())
+    }
+}
+
+impl_into_response_via_rejection!(XForwardedPortRejection);
+
+impl XForwardedPort {
+    /// A new one...
+    pub fn new(forwards: Vec<Option<u16>>) -> Self {
+        Self { forwards }
+    }
+
+    /// A list of ports, `None` for entries which weren't one.
+    pub fn forwards(&self) -> &Vec<Option<u16>> {
+        &self.forwards
+    }
+
+    /// The index in [`XForwardedPort::forwards`] of each entry which wasn't a port.
+    pub fn invalid(&self) -> impl Iterator<Item = usize> {
+        let forwards = self.forwards.iter().enumerate();
+        forwards.filter_map(|(index, port)| port.is_none().then_some(index))
+    }
+
+    /// Parse the header's value.
+    ///
+    /// When strict, the first invalid entry is an error. When lenient, it's kept as a `None`.
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, XForwardedPortRejection> {
+        let forwards = parse_list(s, strictness, |entry| {
+            parse_port(entry).map(Some).ok_or(None)
+        })
+        .map_err(|(index, entry)| XForwardedPortRejection::Invalid(index, String::from(entry)))?;
+        Ok(Self::new(forwards))
+    }
+}
+
+fn parse_port(s: &str) -> Option<u16> {
+    // `u16::from_str` would also take a leading `+`.
+    s.starts_with(|c: char| c.is_ascii_digit())
+        .then(|| s.parse::<u16>().ok())
+        .flatten()
+}
+
+const X_FORWARDED_PORT_HEADER: header::HeaderName =
+    header::HeaderName::from_static("x-forwarded-port");
+
+impl<S> OptionalFromRequestParts<S> for XForwardedPort
+where
+    S: Send + Sync,
+{
+    type Rejection = XForwardedPortRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let header_str = match list_header(&parts.headers, X_FORWARDED_PORT_HEADER) {
+            Ok(Some(header_str)) => header_str,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(XForwardedPortRejection::NotAString),
+        };
+        let strictness = parts
+            .extensions
+            .get::<Strictness>()
+            .copied()
+            .unwrap_or(Strictness::Lenient);
+        XForwardedPort::parse(&header_str, strictness).map(Some)
+    }
+}
+
+impl_required_from_request_parts!(XForwardedPort, XForwardedPortRejection::Missing);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(header: &str) -> Result<Option<XForwardedPort>, XForwardedPortRejection> {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Port", header)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        XForwardedPort::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn simple() {
+        assert_eq!(
+            parse("443, 8080").await,
+            Ok(Some(XForwardedPort {
+                forwards: vec![Some(443), Some(8080)]
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_and_invalid() {
+        let ports = parse(",443,, +443,65536 ,https")
+            .await
+            .expect("lenient parsing should not fail")
+            .expect("no X-Forwarded-Port");
+        assert_eq!(ports.forwards(), &vec![Some(443), None, None, None]);
+        assert_eq!(ports.invalid().collect::<Vec<_>>(), vec![1, 2, 3]);
+        for (header, index, entry) in [("+443", 0, "+443"), ("80, 65536", 1, "65536")] {
+            assert_eq!(
+                XForwardedPort::parse(header, Strictness::Strict),
+                Err(XForwardedPortRejection::Invalid(index, String::from(entry)))
+            );
+        }
+        assert_eq!(
+            XForwardedPort::parse("443,,80", Strictness::Strict),
+            Ok(XForwardedPort::new(vec![Some(443), Some(80)]))
+        );
+    }
+
+    #[tokio::test]
+    async fn not_a_string() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Port", &b"443\xff"[..])
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            XForwardedPort::from_request_parts(&mut parts, &()).await,
+            Err(XForwardedPortRejection::NotAString)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Support for HTTP Header `X-Forwarded-Prefix`, the path a proxy took off the front before
+//! forwarding to us.
+use crate::extract::list_header;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use axum::body::Body;
+use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// Get the contents of the `X-Forwarded-Prefix` header.
+///
+/// If more than one proxy took a prefix off, they're joined in order, e.g. `/a, /b` is `/a/b`.
+/// Prefixes which could take a joined path somewhere else, e.g. with `..`, `//` or control
+/// characters, are rejected, but anyone can send this header: only believe it from proxies you
+/// trust.
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::extract::XForwardedPrefix;
+///
+/// async fn handler(prefix: Option<XForwardedPrefix>) -> String {
+///     match prefix {
+///         Some(prefix) => prefix.join("/invoices"),
+///         None => String::from("/invoices"),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XForwardedPrefix {
+    prefix: String,
+}
+
+/// Why we couldn't extract the `X-Forwarded-Prefix` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum XForwardedPrefixRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// An entry wasn't a safe absolute path.
+    Invalid,
+}
+
+impl fmt::Display for XForwardedPrefixRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing X-Forwarded-Prefix header"),
+            Self::NotAString => {
+                f.write_str("could not parse X-Forwarded-Prefix header into string")
+            }
+            Self::Invalid => f.write_str("could not parse path in X-Forwarded-Prefix header"),
+        }
+    }
+}
+
+impl std::error::Error for XForwardedPrefixRejection {}
+
+impl From<XForwardedPrefixRejection> for Rejection {
+    fn from(value: XForwardedPrefixRejection) -> Self {
+        let kind = match value {
+            XForwardedPrefixRejection::Missing => RejectionKind::Missing,
+            XForwardedPrefixRejection::NotAString => RejectionKind::NotAString,
+            XForwardedPrefixRejection::Invalid => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(X_FORWARDED_PREFIX_HEADER), value.to_string())
+    }
+}
+
+impl_into_response_via_rejection!(XForwardedPrefixRejection);
+
+impl XForwardedPrefix {
+    /// A new one, from an absolute path like `/api/billing`, as long as it's safe to join onto.
+    pub fn new(prefix: &str) -> Result<Self, XForwardedPrefixRejection> {
+        if !is_safe_prefix(prefix) {
+            return Err(XForwardedPrefixRejection::Invalid);
+        }
+        let prefix = String::from(prefix.trim_end_matches('/'));
+        Ok(Self { prefix })
+    }
+
+    /// The prefix, without a trailing slash. Empty if it's just `/`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The external path for `path`, one of our routes. Never scheme-relative, even if `path`
+    /// starts with `//`.
+    pub fn join(&self, path: &str) -> String {
+        match path {
+            "" if self.prefix.is_empty() => String::from("/"),
+            "" => self.prefix.clone(),
+            path if self.prefix.is_empty() => format!("/{}", path.trim_start_matches('/')),
+            path if path.starts_with('/') => format!("{}{path}", self.prefix),
+            path => format!("{}/{path}", self.prefix),
+        }
+    }
+}
+
+const X_FORWARDED_PREFIX_HEADER: header::HeaderName =
+    header::HeaderName::from_static("x-forwarded-prefix");
+
+/// Whether `prefix` is an absolute path that stays on this host and under itself when joined.
+fn is_safe_prefix(prefix: &str) -> bool {
+    if !prefix.starts_with('/') || prefix.starts_with("//") {
+        return false;
+    }
+    if prefix
+        .chars()
+        .any(|c| c.is_control() || matches!(c, '\\' | '?' | '#'))
+    {
+        return false;
+    }
+    !prefix.split('/').any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+impl<S> OptionalFromRequestParts<S> for XForwardedPrefix
+where
+    S: Send + Sync,
+{
+    type Rejection = XForwardedPrefixRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let header_str = match list_header(&parts.headers, X_FORWARDED_PREFIX_HEADER) {
+            Ok(Some(header_str)) => header_str,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(XForwardedPrefixRejection::NotAString),
+        };
+        let mut prefix = String::new();
+        for prefix_raw in header_str.split(',') {
+            let prefix_raw = prefix_raw.trim();
+            if !is_safe_prefix(prefix_raw) {
+                return Err(XForwardedPrefixRejection::Invalid);
+            }
+            prefix.push_str(prefix_raw.trim_end_matches('/'));
+        }
+        Ok(Some(XForwardedPrefix { prefix }))
+    }
+}
+
+impl_required_from_request_parts!(XForwardedPrefix, XForwardedPrefixRejection::Missing);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(header: &str) -> Result<Option<XForwardedPrefix>, XForwardedPrefixRejection> {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Prefix", header)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        XForwardedPrefix::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn simple() {
+        for (header, expected) in [
+            ("/api/billing", "/api/billing"),
+            ("/api/billing/", "/api/billing"),
+            ("/", ""),
+            ("/api, /billing/", "/api/billing"),
+            ("/a%20b/...", "/a%20b/..."),
+        ] {
+            assert_eq!(
+                parse(header).await,
+                Ok(Some(XForwardedPrefix {
+                    prefix: String::from(expected)
+                })),
+                "{header}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        for header in [
+            "",
+            "api",
+            "//evil.example",
+            "/\\evil.example",
+            "https://evil.example",
+            "/api/../admin",
+            "/api/%2E%2e",
+            "/api/./x",
+            "/a\tpi",
+            "/api?x=y",
+            "/api#x",
+            "/api,,/b",
+        ] {
+            assert_eq!(
+                parse(header).await,
+                Err(XForwardedPrefixRejection::Invalid),
+                "{header}"
+            );
+        }
+        assert_eq!(
+            XForwardedPrefix::new("/api/../admin"),
+            Err(XForwardedPrefixRejection::Invalid)
+        );
+    }
+
+    #[test]
+    fn join() {
+        let prefix = XForwardedPrefix::new("/api/billing/").expect("could not make prefix");
+        assert_eq!(
+            prefix.join("/invoices?page=2"),
+            "/api/billing/invoices?page=2"
+        );
+        assert_eq!(prefix.join("invoices"), "/api/billing/invoices");
+        assert_eq!(prefix.join("/"), "/api/billing/");
+        assert_eq!(prefix.join(""), "/api/billing");
+        let root = XForwardedPrefix::new("/").expect("could not make prefix");
+        assert_eq!(root.join(""), "/");
+        assert_eq!(root.join("/invoices"), "/invoices");
+        assert_eq!(root.join("//evil.example"), "/evil.example");
+    }
+}
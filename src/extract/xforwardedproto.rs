@@ -0,0 +1,217 @@
+//! Support for HTTP Header [`X-Forwarded-Proto`][mdn].
+//!
+//! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/X-Forwarded-Proto
+use crate::Strictness;
+use crate::extract::forwarded::Protocol;
+use crate::extract::rejection::{
+    Rejection, RejectionKind, impl_into_response_via_rejection, impl_required_from_request_parts,
+};
+use crate::extract::{list_header, parse_list};
+use axum::body::Body;
+use axum::extract::OptionalFromRequestParts;
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::fmt;
+
+/// Get the contents of the `X-Forwarded-Proto` header.
+///
+/// Each proxy which added to it is a hop, with the client's closest proxy first, like
+/// `X-Forwarded-For`. Empty entries are skipped.
+///
+/// An entry which isn't a URI scheme is rejected if there's a [`Strictness::Strict`] in the
+/// request's extensions. Otherwise it's kept as a [`Protocol::Other`]; see
+/// [`XForwardedProto::invalid`].
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::extract::XForwardedProto;
+///
+/// async fn handler(xforwarded: Option<XForwardedProto>) {
+///     todo!()
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct XForwardedProto {
+    forwards: Vec<Protocol>,
+}
+
+/// Why we couldn't extract the `X-Forwarded-Proto` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum XForwardedProtoRejection {
+    /// There wasn't one, and it's required.
+    Missing,
+    /// The header's gotta be at least UTF-8.
+    NotAString,
+    /// This entry (counting from `0`, skipping empty ones) isn't a URI scheme, and we're being
+    /// strict about it.
+    Invalid(usize, String),
+}
+
+impl fmt::Display for XForwardedProtoRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("missing X-Forwarded-Proto header"),
+            Self::NotAString => f.write_str("could not parse X-Forwarded-Proto header into string"),
+            Self::Invalid(index, entry) => write!(
+                f,
+                "X-Forwarded-Proto entry {index}, `{entry}`: not a URI scheme"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for XForwardedProtoRejection {}
+
+impl From<XForwardedProtoRejection> for Rejection {
+    fn from(value: XForwardedProtoRejection) -> Self {
+        let kind = match value {
+            XForwardedProtoRejection::Missing => RejectionKind::Missing,
+            XForwardedProtoRejection::NotAString => RejectionKind::NotAString,
+            XForwardedProtoRejection::Invalid(..) => RejectionKind::Invalid,
+        };
+        Rejection::new(kind, Some(X_FORWARDED_PROTO_HEADER), value.to_string())
+    }
+}
+
+impl_into_response_via_rejection!(XForwardedProtoRejection);
+
+impl XForwardedProto {
+    /// A new one...
+    pub fn new(forwards: Vec<Protocol>) -> Self {
+        Self { forwards }
+    }
+
+    /// A list of [`Protocol`]s.
+    pub fn forwards(&self) -> &Vec<Protocol> {
+        &self.forwards
+    }
+
+    /// The entries which weren't a URI scheme, with their index in [`XForwardedProto::forwards`].
+    pub fn invalid(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.forwards
+            .iter()
+            .enumerate()
+            .filter_map(|(index, forward)| match forward {
+                Protocol::Other(entry) if !is_scheme(entry) => Some((index, entry.as_str())),
+                _ => None,
+            })
+    }
+
+    /// Parse the header's value.
+    ///
+    /// When strict, the first invalid entry is an error. When lenient, it's kept as a
+    /// [`Protocol::Other`].
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, XForwardedProtoRejection> {
+        let forwards = parse_list(s, strictness, |entry| {
+            let Ok(proto) = entry.parse::<Protocol>();
+            if is_scheme(entry) {
+                Ok(proto)
+            } else {
+                Err(proto)
+            }
+        })
+        .map_err(|(index, entry)| XForwardedProtoRejection::Invalid(index, String::from(entry)))?;
+        Ok(Self::new(forwards))
+    }
+}
+
+/// `ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )`, as per
+/// [RFC 3986 §3.1](https://www.rfc-editor.org/rfc/rfc3986#section-3.1).
+fn is_scheme(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+const X_FORWARDED_PROTO_HEADER: header::HeaderName =
+    header::HeaderName::from_static("x-forwarded-proto");
+
+impl<S> OptionalFromRequestParts<S> for XForwardedProto
+where
+    S: Send + Sync,
+{
+    type Rejection = XForwardedProtoRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let header_str = match list_header(&parts.headers, X_FORWARDED_PROTO_HEADER) {
+            Ok(Some(header_str)) => header_str,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(XForwardedProtoRejection::NotAString),
+        };
+        let strictness = parts
+            .extensions
+            .get::<Strictness>()
+            .copied()
+            .unwrap_or(Strictness::Lenient);
+        XForwardedProto::parse(&header_str, strictness).map(Some)
+    }
+}
+
+impl_required_from_request_parts!(XForwardedProto, XForwardedProtoRejection::Missing);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(header: &str) -> Result<Option<XForwardedProto>, XForwardedProtoRejection> {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Proto", header)
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        XForwardedProto::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn simple() {
+        assert_eq!(
+            parse("HTTPS, http,ws").await,
+            Ok(Some(XForwardedProto {
+                forwards: vec![
+                    Protocol::Https,
+                    Protocol::Http,
+                    Protocol::Other(String::from("ws"))
+                ]
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_and_invalid() {
+        assert_eq!(
+            parse(",https").await,
+            Ok(Some(XForwardedProto::new(vec![Protocol::Https])))
+        );
+        assert_eq!(parse("").await, Ok(Some(XForwardedProto::new(vec![]))));
+        let protos = parse("https,,h/2, http")
+            .await
+            .expect("lenient parsing should not fail")
+            .expect("no X-Forwarded-Proto");
+        assert_eq!(protos.forwards().len(), 3);
+        assert_eq!(protos.invalid().collect::<Vec<_>>(), vec![(1, "h/2")]);
+        assert_eq!(
+            XForwardedProto::parse("https, 2http", Strictness::Strict),
+            Err(XForwardedProtoRejection::Invalid(1, String::from("2http")))
+        );
+    }
+
+    #[tokio::test]
+    async fn not_a_string() {
+        let (mut parts, _) = axum::http::request::Builder::new()
+            .method("GET")
+            .header("X-Forwarded-Proto", &b"https\xff"[..])
+            .body(())
+            .expect("could not build request")
+            .into_parts();
+        assert_eq!(
+            XForwardedProto::from_request_parts(&mut parts, &()).await,
+            Err(XForwardedProtoRejection::NotAString)
+        );
+    }
+}
@@ -2,4 +2,23 @@
 
 #[deny(missing_docs)]
 pub mod extract;
+#[deny(missing_docs)]
+pub mod middleware;
+#[deny(missing_docs)]
+pub mod network;
 pub mod proxy;
+
+/// How closely to follow the spec when parsing what a proxy sent us.
+///
+/// Everything is lenient unless told otherwise, as the PROXY listener always was: use
+/// [`proxy::Listener::strictness`] for the listener, and put a `Strictness` in the request's
+/// extensions (e.g. with `.layer(axum::Extension(Strictness::Strict))`) for the header
+/// extractors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Reject anything the spec doesn't allow.
+    Strict,
+    /// Accept what we can make sense of, for senders which don't quite follow the spec.
+    #[default]
+    Lenient,
+}
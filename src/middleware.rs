@@ -0,0 +1,13 @@
+//! Middleware for services which sit behind, or act as, a reverse proxy.
+
+pub mod forwarded;
+pub mod ratelimit;
+pub mod rejection;
+pub mod requirehttps;
+pub mod stripuntrusted;
+
+pub use crate::middleware::forwarded::*;
+pub use crate::middleware::ratelimit::*;
+pub use crate::middleware::rejection::*;
+pub use crate::middleware::requirehttps::*;
+pub use crate::middleware::stripuntrusted::*;
@@ -0,0 +1,272 @@
+//! Appends a hop to the [`Forwarded`][mdn] header of requests on their way to another backend.
+//!
+//! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Forwarded
+use crate::extract::forwarded::{Forward, Interface, Protocol};
+use crate::proxy;
+use axum::extract::ConnectInfo;
+use axum::http::header::{self, HeaderName, HeaderValue};
+use axum::http::{HeaderMap, Request};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+const X_FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+#[derive(Clone, Debug, Default)]
+struct Config {
+    by: Option<Interface>,
+    host: Option<String>,
+    proto: Option<Protocol>,
+    x_forwarded_for: bool,
+}
+
+/// Adds our hop to the `Forwarded` header, for when we're the proxy.
+///
+/// * `for` is the connection's address, from [`ConnectInfo<proxy::Addr>`][proxy::Addr] or
+///   [`ConnectInfo<SocketAddr>`], or `unknown` if there's neither;
+/// * `by` is as configured, or where the PROXY header says the connection was headed;
+/// * `host` is as configured, or the request's `Host`; and
+/// * `proto` is as configured, or the request URI's scheme.
+///
+/// Existing `Forwarded` headers are kept, and the new element goes on the end.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Router, routing::any};
+/// use axum_proxied::extract::forwarded::Protocol;
+/// use axum_proxied::middleware::AppendForwardedLayer;
+///
+/// async fn upstream() {
+///     todo!()
+/// }
+///
+/// let app: Router = Router::new().route("/", any(upstream)).layer(
+///     AppendForwardedLayer::new()
+///         .proto(Protocol::Https)
+///         .x_forwarded_for(true),
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AppendForwardedLayer {
+    config: Arc<Config>,
+}
+
+impl AppendForwardedLayer {
+    /// Derive everything from the request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this as `by`, e.g. an obfuscated name for ourselves.
+    pub fn by(mut self, by: Interface) -> Self {
+        Arc::make_mut(&mut self.config).by = Some(by);
+        self
+    }
+
+    /// Use this as `host`, instead of the request's `Host`.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.config).host = Some(host.into());
+        self
+    }
+
+    /// Use this as `proto`, e.g. when TLS is terminated before it gets to us.
+    pub fn proto(mut self, proto: Protocol) -> Self {
+        Arc::make_mut(&mut self.config).proto = Some(proto);
+        self
+    }
+
+    /// Also append the client's IP to `X-Forwarded-For`.
+    pub fn x_forwarded_for(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).x_forwarded_for = enabled;
+        self
+    }
+}
+
+impl<S> Layer<S> for AppendForwardedLayer {
+    type Service = AppendForwarded<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AppendForwarded {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// See [`AppendForwardedLayer`].
+#[derive(Clone, Debug)]
+pub struct AppendForwarded<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+impl<S, B> Service<Request<B>> for AppendForwarded<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        self.config.append(&mut request);
+        self.inner.call(request)
+    }
+}
+
+impl Config {
+    fn append<B>(&self, request: &mut Request<B>) {
+        let extensions = request.extensions();
+        let proxied = extensions
+            .get::<ConnectInfo<proxy::Addr>>()
+            .map(|ConnectInfo(addr)| addr);
+        let peer = proxied.map(|addr| addr.source()).or_else(|| {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr)
+        });
+        let by = self.by.clone().or_else(|| {
+            proxied
+                .map(|addr| addr.destination())
+                .filter(|destination| !destination.ip().is_unspecified())
+                .map(Interface::Socket)
+        });
+        let host = self.host.clone().or_else(|| {
+            request
+                .headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .or_else(|| request.uri().authority().map(|a| a.as_str()))
+                .map(String::from)
+        });
+        let proto = self.proto.clone().or_else(|| {
+            request
+                .uri()
+                .scheme_str()
+                .and_then(|s| s.parse::<Protocol>().ok())
+        });
+        let r#for = peer.map(Interface::Socket).unwrap_or(Interface::Unknown);
+        let forward = Forward::new(by, Some(r#for), host, proto);
+        let Ok(element) = HeaderValue::try_from(&forward) else {
+            // Configured values that can't go in a header; better to add nothing than garbage.
+            return;
+        };
+        let headers = request.headers_mut();
+        append_to_list(headers, header::FORWARDED, element);
+        if let (true, Some(peer)) = (self.x_forwarded_for, peer) {
+            append_to_list(headers, X_FORWARDED_FOR_HEADER, ip_header_value(peer.ip()));
+        }
+    }
+}
+
+fn ip_header_value(ip: IpAddr) -> HeaderValue {
+    HeaderValue::try_from(ip.to_string()).expect("IPs are always valid header values")
+}
+
+/// Add `value` to the end of a comma-separated list header, folding any existing lines into one.
+fn append_to_list(headers: &mut HeaderMap, name: HeaderName, value: HeaderValue) {
+    let mut combined: Vec<u8> = vec![];
+    for existing in headers.get_all(&name) {
+        combined.extend_from_slice(existing.as_bytes());
+        combined.extend_from_slice(b", ");
+    }
+    combined.extend_from_slice(value.as_bytes());
+    let value = HeaderValue::from_bytes(&combined)
+        .expect("valid header values joined with \", \" are still a valid header value");
+    headers.insert(name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::{ServiceExt, service_fn};
+
+    async fn forward(layer: AppendForwardedLayer, request: Request<()>) -> HeaderMap {
+        let service = layer.layer(service_fn(|request: Request<()>| async move {
+            Ok::<_, Infallible>(request.headers().clone())
+        }));
+        service.oneshot(request).await.expect("infallible")
+    }
+
+    #[tokio::test]
+    async fn appends() {
+        let request = Request::builder()
+            .uri("/")
+            .header("Host", "example.com")
+            .header("Forwarded", "for=192.0.2.60")
+            .header("Forwarded", r#"for="[2001:db8::1]""#)
+            .header("X-Forwarded-For", "192.0.2.60")
+            .extension(ConnectInfo(
+                "198.51.100.17:4711".parse::<SocketAddr>().expect("???"),
+            ))
+            .body(())
+            .expect("could not build request");
+        let headers = forward(
+            AppendForwardedLayer::new()
+                .by(Interface::Obfuscated {
+                    node: String::from("_us"),
+                    port: None,
+                })
+                .proto(Protocol::Https)
+                .x_forwarded_for(true),
+            request,
+        )
+        .await;
+        assert_eq!(
+            headers
+                .get_all(header::FORWARDED)
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![
+                r#"for=192.0.2.60, for="[2001:db8::1]", by=_us;for="198.51.100.17:4711";host=example.com;proto=https"#
+            ]
+        );
+        assert_eq!(
+            headers
+                .get(X_FORWARDED_FOR_HEADER)
+                .expect("no X-Forwarded-For"),
+            "192.0.2.60, 198.51.100.17"
+        );
+    }
+
+    #[tokio::test]
+    async fn from_proxy_addr() {
+        let request = Request::builder()
+            .uri("http://example.com:8080/")
+            .extension(ConnectInfo(proxy::Addr::new(
+                "[2001:db8::17]:4711".parse::<SocketAddr>().expect("???"),
+                "10.0.0.1:443".parse::<SocketAddr>().expect("???"),
+            )))
+            .body(())
+            .expect("could not build request");
+        let headers = forward(AppendForwardedLayer::new(), request).await;
+        assert_eq!(
+            headers.get(header::FORWARDED).expect("no Forwarded"),
+            r#"by="10.0.0.1:443";for="[2001:db8::17]:4711";host="example.com:8080";proto=http"#
+        );
+        assert_eq!(headers.get(X_FORWARDED_FOR_HEADER), None);
+    }
+
+    #[tokio::test]
+    async fn unknown_peer() {
+        let request = Request::builder()
+            .uri("/")
+            .body(())
+            .expect("could not build request");
+        let headers = forward(AppendForwardedLayer::new().x_forwarded_for(true), request).await;
+        assert_eq!(
+            headers.get(header::FORWARDED).expect("no Forwarded"),
+            "for=unknown"
+        );
+        assert_eq!(headers.get(X_FORWARDED_FOR_HEADER), None);
+    }
+}
@@ -0,0 +1,397 @@
+//! Rate limits each client, by the IP the proxies we trust say it has rather than theirs.
+use crate::extract::clientip::ClientIp;
+use crate::network::Network;
+use axum::extract::FromRequestParts;
+use axum::http::header::{self, HeaderValue};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// Where it is in [`Buckets::by_full_at`].
+    slot: (Duration, u64),
+}
+
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<Network, Bucket>,
+    /// Which bucket will be full again when, as time since `epoch`, soonest first. The counter
+    /// tells apart buckets which fill up at the same time.
+    by_full_at: BTreeMap<(Duration, u64), Network>,
+    epoch: Instant,
+    counter: u64,
+}
+
+impl Buckets {
+    fn new() -> Self {
+        Self {
+            clients: HashMap::new(),
+            by_full_at: BTreeMap::new(),
+            epoch: Instant::now(),
+            counter: 0,
+        }
+    }
+
+    /// Forget the bucket which will be full again soonest. If it already is, it's the same as no
+    /// bucket at all; otherwise, it's the client we let off the lightest.
+    fn evict(&mut self, now: Duration) {
+        let Some(((full_at, _), key)) = self.by_full_at.pop_first() else {
+            return;
+        };
+        if cfg!(feature = "tracing") && full_at > now {
+            tracing::debug!("rate limiter is full, forgetting a client which is still limited");
+        }
+        self.clients.remove(&key);
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    /// Tokens per second.
+    rate: f64,
+    burst: f64,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    max_clients: usize,
+}
+
+/// Limits how often each client can make requests, with a token bucket per client.
+///
+/// The client is the [`ClientIp`], so put the
+/// [`TrustedProxies`](crate::extract::TrustedProxies) in the request's extensions outside of
+/// this layer, or everyone behind the load balancer shares one bucket. IPv6 clients are grouped
+/// by their `/64` by default, since that's what one usually gets.
+///
+/// Each bucket holds up to `burst` requests (by default, as many as are allowed per period) and
+/// refills at the configured rate. When it's empty, the response is `429 Too Many Requests`
+/// with a `Retry-After`. At most [`RateLimitLayer::max_clients`] buckets are kept; when there's
+/// no room, the one which will be full again soonest is forgotten, so clients who are being
+/// limited stay limited.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Extension, Router, routing::get};
+/// use axum_proxied::extract::TrustedProxies;
+/// use axum_proxied::middleware::RateLimitLayer;
+/// use std::time::Duration;
+///
+/// async fn handler() {
+///     todo!()
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(RateLimitLayer::new(60, Duration::from_secs(60)).burst(10))
+///     .layer(Extension(TrustedProxies::hops(1)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    config: Arc<Config>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimitLayer {
+    /// Allow each client `requests` every `per`. Both are at least one (request, or nanosecond).
+    pub fn new(requests: u32, per: Duration) -> Self {
+        let requests = requests.max(1);
+        let per = per.max(Duration::from_nanos(1));
+        Self {
+            config: Arc::new(Config {
+                rate: f64::from(requests) / per.as_secs_f64(),
+                burst: f64::from(requests),
+                ipv4_prefix: 32,
+                ipv6_prefix: 64,
+                max_clients: 10_000,
+            }),
+            buckets: Arc::new(Mutex::new(Buckets::new())),
+        }
+    }
+
+    /// Let a client make up to this many requests at once; at least one.
+    pub fn burst(mut self, burst: u32) -> Self {
+        Arc::make_mut(&mut self.config).burst = f64::from(burst.max(1));
+        self
+    }
+
+    /// Share a bucket between IPv4 clients in the same network of this size; `32` by default.
+    pub fn ipv4_prefix(mut self, prefix: u8) -> Self {
+        Arc::make_mut(&mut self.config).ipv4_prefix = prefix.min(32);
+        self
+    }
+
+    /// Share a bucket between IPv6 clients in the same network of this size; `64` by default.
+    pub fn ipv6_prefix(mut self, prefix: u8) -> Self {
+        Arc::make_mut(&mut self.config).ipv6_prefix = prefix.min(128);
+        self
+    }
+
+    /// Keep at most this many buckets; `10000` by default.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_clients = max_clients.max(1);
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            config: self.config.clone(),
+            buckets: self.buckets.clone(),
+        }
+    }
+}
+
+/// See [`RateLimitLayer`].
+#[derive(Clone, Debug)]
+pub struct RateLimit<S> {
+    inner: S,
+    config: Arc<Config>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // The one which is ready goes in the future; the clone stays for next time.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let buckets = self.buckets.clone();
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let client = match ClientIp::from_request_parts(&mut parts, &()).await {
+                Ok(client) => client,
+                Err(rejection) => return Ok(rejection.into_response()),
+            };
+            if let Err(wait) = config.acquire(&buckets, client.ip(), Instant::now()) {
+                return Ok(too_many_requests(wait));
+            }
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    // Whole seconds, rounded up so that retrying on time works.
+    let seconds = wait
+        .as_secs()
+        .saturating_add(u64::from(wait.subsec_nanos() > 0));
+    let retry_after = HeaderValue::from(seconds);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after)],
+        "too many requests",
+    )
+        .into_response()
+}
+
+impl Config {
+    fn key(&self, ip: IpAddr) -> Network {
+        let ip = ip.to_canonical();
+        let prefix = match ip {
+            IpAddr::V4(_) => self.ipv4_prefix,
+            IpAddr::V6(_) => self.ipv6_prefix,
+        };
+        Network::new(ip, prefix).expect("prefixes are clamped to the address width")
+    }
+
+    /// Take a token from `ip`'s bucket, or say how long until there'll be one.
+    fn acquire(&self, buckets: &Mutex<Buckets>, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let key = self.key(ip);
+        let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let since_epoch = now.saturating_duration_since(buckets.epoch);
+        let mut bucket = match buckets.clients.remove(&key) {
+            Some(bucket) => {
+                buckets.by_full_at.remove(&bucket.slot);
+                bucket
+            }
+            None => {
+                if buckets.clients.len() >= self.max_clients {
+                    buckets.evict(since_epoch);
+                }
+                Bucket {
+                    tokens: self.burst,
+                    last: now,
+                    slot: (since_epoch, 0),
+                }
+            }
+        };
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+        let acquired = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.refill(1.0 - bucket.tokens))
+        };
+        buckets.counter = buckets.counter.wrapping_add(1);
+        let full_at = since_epoch.saturating_add(self.refill(self.burst - bucket.tokens));
+        bucket.slot = (full_at, buckets.counter);
+        buckets.by_full_at.insert(bucket.slot, key);
+        buckets.clients.insert(key, bucket);
+        acquired
+    }
+
+    /// How long until `tokens` more are in a bucket.
+    fn refill(&self, tokens: f64) -> Duration {
+        Duration::try_from_secs_f64(tokens.max(0.0) / self.rate).unwrap_or(Duration::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tower::{ServiceExt, service_fn};
+
+    fn acquire(layer: &RateLimitLayer, ip: &str, now: Instant) -> Result<(), Duration> {
+        let ip = ip.parse::<IpAddr>().expect("???");
+        layer.config.acquire(&layer.buckets, ip, now)
+    }
+
+    #[test]
+    fn bucket() {
+        let layer = RateLimitLayer::new(1, Duration::from_secs(2)).burst(2);
+        let start = Instant::now();
+        assert_eq!(acquire(&layer, "192.0.2.1", start), Ok(()));
+        assert_eq!(acquire(&layer, "192.0.2.1", start), Ok(()));
+        assert_eq!(
+            acquire(&layer, "192.0.2.1", start),
+            Err(Duration::from_secs(2))
+        );
+        // Someone else has their own bucket.
+        assert_eq!(acquire(&layer, "192.0.2.2", start), Ok(()));
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            acquire(&layer, "192.0.2.1", later),
+            Err(Duration::from_secs(1))
+        );
+        let later = start + Duration::from_secs(2);
+        assert_eq!(acquire(&layer, "192.0.2.1", later), Ok(()));
+    }
+
+    #[test]
+    fn ipv6_prefix() {
+        let layer = RateLimitLayer::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert_eq!(acquire(&layer, "2001:db8::1", now), Ok(()));
+        assert!(acquire(&layer, "2001:db8::ffff:2", now).is_err());
+        assert_eq!(acquire(&layer, "2001:db8:0:1::1", now), Ok(()));
+        // Mapped IPv4 addresses aren't lumped together by the IPv6 prefix.
+        assert_eq!(acquire(&layer, "::ffff:192.0.2.1", now), Ok(()));
+        assert!(acquire(&layer, "192.0.2.1", now).is_err());
+        assert_eq!(acquire(&layer, "::ffff:192.0.2.2", now), Ok(()));
+    }
+
+    #[test]
+    fn bounded() {
+        let layer = RateLimitLayer::new(1, Duration::from_secs(60)).max_clients(2);
+        let start = Instant::now();
+        assert_eq!(acquire(&layer, "192.0.2.1", start), Ok(()));
+        let later = start + Duration::from_secs(1);
+        assert_eq!(acquire(&layer, "192.0.2.2", later), Ok(()));
+        assert_eq!(acquire(&layer, "192.0.2.3", later), Ok(()));
+        let buckets = layer.buckets.lock().expect("poisoned");
+        assert_eq!(buckets.clients.len(), 2);
+        assert_eq!(buckets.by_full_at.len(), 2);
+        let first = "192.0.2.1".parse::<IpAddr>().expect("???");
+        assert!(!buckets.clients.contains_key(&Network::from(first)));
+    }
+
+    #[test]
+    fn eviction_keeps_limited_clients() {
+        let layer = RateLimitLayer::new(5, Duration::from_secs(300)).max_clients(2);
+        let start = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(acquire(&layer, "192.0.2.1", start), Ok(()));
+        }
+        assert!(acquire(&layer, "192.0.2.1", start).is_err());
+        // Plenty of others come and go while it's limited.
+        for i in 0..100 {
+            let later = start + Duration::from_secs(1);
+            assert_eq!(acquire(&layer, &format!("198.51.100.{i}"), later), Ok(()));
+        }
+        let later = start + Duration::from_secs(2);
+        assert!(acquire(&layer, "192.0.2.1", later).is_err());
+    }
+
+    #[test]
+    fn degenerate() {
+        // None of these should panic, or lock everyone out for good.
+        let layer = RateLimitLayer::new(0, Duration::ZERO).burst(0);
+        let now = Instant::now();
+        assert_eq!(acquire(&layer, "192.0.2.1", now), Ok(()));
+        let layer = RateLimitLayer::new(1, Duration::MAX);
+        assert_eq!(acquire(&layer, "192.0.2.1", now), Ok(()));
+        assert!(acquire(&layer, "192.0.2.1", now).is_err());
+        let response = too_many_requests(Duration::MAX);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER),
+            Some(&HeaderValue::from(u64::MAX))
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after() {
+        let service =
+            RateLimitLayer::new(1, Duration::from_secs(90)).layer(service_fn(
+                |_: Request<()>| async move {
+                    Ok::<_, Infallible>(StatusCode::NO_CONTENT.into_response())
+                },
+            ));
+        let request = || {
+            Request::builder()
+                .extension(ConnectInfo(
+                    "192.0.2.1:1234".parse::<SocketAddr>().expect("???"),
+                ))
+                .body(())
+                .expect("could not build request")
+        };
+        let response = service
+            .clone()
+            .oneshot(request())
+            .await
+            .expect("infallible");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = service.oneshot(request()).await.expect("infallible");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .expect("no Retry-After");
+        assert!((89..=90).contains(&retry_after), "{retry_after}");
+    }
+}
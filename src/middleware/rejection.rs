@@ -0,0 +1,209 @@
+//! Answers this crate's extractor rejections however the application likes.
+use crate::extract::rejection::Rejection;
+use axum::http::Request;
+use axum::response::Response;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Turns a [`Rejection`] into a response. Implemented for closures.
+pub trait RejectionResponder: Send + Sync + 'static {
+    /// The response to send instead of the default.
+    fn respond(&self, rejection: &Rejection) -> Response;
+}
+
+impl<F> RejectionResponder for F
+where
+    F: Fn(&Rejection) -> Response + Send + Sync + 'static,
+{
+    fn respond(&self, rejection: &Rejection) -> Response {
+        self(rejection)
+    }
+}
+
+/// Replaces the responses for rejections from this crate's extractors, e.g. with JSON problem
+/// details. Other responses are left alone.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::http::header::CONTENT_TYPE;
+/// use axum::response::IntoResponse;
+/// use axum::{Router, routing::get};
+/// use axum_proxied::extract::{Forwarded, Rejection};
+/// use axum_proxied::middleware::RejectionResponseLayer;
+///
+/// async fn handler(forwarded: Forwarded) {
+///     todo!()
+/// }
+///
+/// let app: Router = Router::new().route("/", get(handler)).layer(RejectionResponseLayer::new(
+///     |rejection: &Rejection| {
+///         let body = format!(
+///             r#"{{"status":{},"title":"{:?}"}}"#,
+///             rejection.status().as_u16(),
+///             rejection.kind(),
+///         );
+///         let headers = [(CONTENT_TYPE, "application/problem+json")];
+///         (rejection.status(), headers, body).into_response()
+///     },
+/// ));
+/// ```
+pub struct RejectionResponseLayer<R> {
+    responder: Arc<R>,
+}
+
+impl<R: RejectionResponder> RejectionResponseLayer<R> {
+    /// Answer rejections with `responder`.
+    pub fn new(responder: R) -> Self {
+        Self {
+            responder: Arc::new(responder),
+        }
+    }
+}
+
+impl<R> Clone for RejectionResponseLayer<R> {
+    fn clone(&self) -> Self {
+        Self {
+            responder: self.responder.clone(),
+        }
+    }
+}
+
+impl<S, R> Layer<S> for RejectionResponseLayer<R> {
+    type Service = RejectionResponse<S, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RejectionResponse {
+            inner,
+            responder: self.responder.clone(),
+        }
+    }
+}
+
+/// See [`RejectionResponseLayer`].
+pub struct RejectionResponse<S, R> {
+    inner: S,
+    responder: Arc<R>,
+}
+
+impl<S: Clone, R> Clone for RejectionResponse<S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            responder: self.responder.clone(),
+        }
+    }
+}
+
+impl<S, R, B> Service<Request<B>> for RejectionResponse<S, R>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+    R: RejectionResponder,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let response = self.inner.call(request);
+        let responder = self.responder.clone();
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(match response.extensions().get::<Rejection>() {
+                Some(rejection) => responder.respond(rejection),
+                None => response,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{Forwarded, RejectionKind};
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{Router, body};
+    use tower::ServiceExt;
+
+    async fn get_body(app: Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = app.oneshot(request).await.expect("infallible");
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("could not read body");
+        (
+            status,
+            String::from_utf8(bytes.to_vec()).expect("not UTF-8"),
+        )
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|_: Forwarded| async { "ok" }))
+            .route("/teapot", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .layer(RejectionResponseLayer::new(|rejection: &Rejection| {
+                let body = format!(
+                    "{:?} {}",
+                    rejection.kind(),
+                    rejection.header().map(|h| h.as_str()).unwrap_or("-")
+                );
+                (StatusCode::UNPROCESSABLE_ENTITY, body).into_response()
+            }))
+    }
+
+    #[tokio::test]
+    async fn default_response() {
+        let app = Router::new().route("/", get(|_: Forwarded| async { "ok" }));
+        let request = Request::new(Body::empty());
+        assert_eq!(
+            get_body(app, request).await,
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("missing Forwarded header")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_response() {
+        let request = Request::new(Body::empty());
+        assert_eq!(
+            get_body(app(), request).await,
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("{:?} forwarded", RejectionKind::Missing)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn other_responses_untouched() {
+        let request = Request::builder()
+            .uri("/")
+            .header("Forwarded", "for=192.0.2.60")
+            .body(Body::empty())
+            .expect("could not build request");
+        assert_eq!(
+            get_body(app(), request).await,
+            (StatusCode::OK, String::from("ok"))
+        );
+        let request = Request::builder()
+            .uri("/teapot")
+            .body(Body::empty())
+            .expect("could not build request");
+        assert_eq!(get_body(app(), request).await.0, StatusCode::IM_A_TEAPOT);
+    }
+}
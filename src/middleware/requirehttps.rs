@@ -0,0 +1,354 @@
+//! Sends clients to HTTPS when TLS is terminated in front of us, so all we ever see is HTTP.
+use crate::extract::externalurl::ExternalUrl;
+use crate::extract::rejection::{Rejection, RejectionKind};
+use axum::extract::FromRequestParts;
+use axum::http::header::{self, HeaderValue};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[derive(Clone, Debug)]
+struct Config {
+    redirect: bool,
+    hsts: Option<HeaderValue>,
+}
+
+/// Redirects or rejects requests the client didn't make over HTTPS.
+///
+/// The scheme the client used is the one [`ExternalUrl`] works out, so only
+/// [`TrustedProxies`](crate::extract::TrustedProxies) get a say, and anything else is HTTP. Put
+/// the trusted proxies and the [`AllowedHosts`](crate::extract::AllowedHosts) to redirect to in
+/// the request's extensions outside of this layer; without the latter, every request is rejected
+/// as misconfigured. Don't use this if TLS ends at us, since every request would look like HTTP.
+///
+/// Redirects are `308 Permanent Redirect`, to the same URL with `https` and the default port.
+/// Rejections are a [`Rejection`] with [`RejectionKind::NotAllowed`], so
+/// [`RejectionResponseLayer`](crate::middleware::RejectionResponseLayer) can answer them.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Extension, Router, routing::get};
+/// use axum_proxied::extract::{AllowedHosts, TrustedProxies};
+/// use axum_proxied::middleware::RequireHttpsLayer;
+/// use std::time::Duration;
+///
+/// async fn handler() {
+///     todo!()
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(RequireHttpsLayer::redirect().hsts(Duration::from_secs(31536000), true))
+///     .layer(Extension(AllowedHosts::new(["example.com"]).unwrap()))
+///     .layer(Extension(TrustedProxies::hops(1)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RequireHttpsLayer {
+    config: Arc<Config>,
+}
+
+impl RequireHttpsLayer {
+    /// Redirect HTTP requests to HTTPS.
+    pub fn redirect() -> Self {
+        Self {
+            config: Arc::new(Config {
+                redirect: true,
+                hsts: None,
+            }),
+        }
+    }
+
+    /// Reject HTTP requests.
+    pub fn reject() -> Self {
+        Self {
+            config: Arc::new(Config {
+                redirect: false,
+                hsts: None,
+            }),
+        }
+    }
+
+    /// Add a `Strict-Transport-Security` header to responses to HTTPS requests, telling browsers
+    /// to only use HTTPS for `max_age`, and for subdomains too if `include_subdomains`.
+    pub fn hsts(mut self, max_age: Duration, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        Arc::make_mut(&mut self.config).hsts =
+            Some(HeaderValue::try_from(value).expect("always a valid header value"));
+        self
+    }
+}
+
+impl<S> Layer<S> for RequireHttpsLayer {
+    type Service = RequireHttps<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireHttps {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// See [`RequireHttpsLayer`].
+#[derive(Clone, Debug)]
+pub struct RequireHttps<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+impl<S, B> Service<Request<B>> for RequireHttps<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // The one which is ready goes in the future; the clone stays for next time.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let url = match ExternalUrl::from_request_parts(&mut parts, &()).await {
+                Ok(url) => url,
+                Err(rejection) => return Ok(rejection.into_response()),
+            };
+            if url.uri().scheme_str() != Some("https") {
+                return Ok(config.insecure(&url));
+            }
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(hsts) = &config.hsts {
+                response
+                    .headers_mut()
+                    .insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+            }
+            Ok(response)
+        })
+    }
+}
+
+impl Config {
+    fn insecure(&self, url: &ExternalUrl) -> Response {
+        let uri = url.uri();
+        let location = match (self.redirect, uri.host()) {
+            (true, Some(host)) => {
+                let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+                HeaderValue::try_from(format!("https://{host}{path_and_query}")).ok()
+            }
+            _ => None,
+        };
+        match location {
+            Some(location) => (
+                StatusCode::PERMANENT_REDIRECT,
+                [(header::LOCATION, location)],
+            )
+                .into_response(),
+            None => {
+                Rejection::new(RejectionKind::NotAllowed, None, "HTTPS is required").into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{AllowedHosts, HopHeader, TrustedProxies};
+    use axum::extract::ConnectInfo;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tower::{ServiceExt, service_fn};
+
+    async fn require(layer: RequireHttpsLayer, headers: &[(&str, &str)]) -> Response {
+        require_with(layer, TrustedProxies::hops(1), headers).await
+    }
+
+    async fn require_with(
+        layer: RequireHttpsLayer,
+        trusted: TrustedProxies,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        let service = layer.layer(service_fn(|_: Request<()>| async move {
+            Ok::<_, Infallible>(StatusCode::NO_CONTENT.into_response())
+        }));
+        let mut builder = Request::builder()
+            .uri("/a?b=c")
+            .extension(ConnectInfo(
+                "10.0.0.1:1234".parse::<SocketAddr>().expect("???"),
+            ))
+            .extension(trusted)
+            .extension(AllowedHosts::new(["example.com"]).expect("???"));
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder.body(()).expect("could not build request");
+        service.oneshot(request).await.expect("infallible")
+    }
+
+    #[tokio::test]
+    async fn redirects() {
+        let response = require(
+            RequireHttpsLayer::redirect(),
+            &[
+                ("Host", "internal"),
+                (
+                    "Forwarded",
+                    "for=192.0.2.1;host=\"example.com:8080\";proto=http",
+                ),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers().get(header::LOCATION),
+            Some(&HeaderValue::from_static("https://example.com/a?b=c"))
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects() {
+        // Without a proto from a trusted hop, it's whatever we saw: HTTP.
+        let response = require(RequireHttpsLayer::reject(), &[("Host", "example.com")]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response
+                .extensions()
+                .get::<Rejection>()
+                .map(Rejection::kind),
+            Some(RejectionKind::NotAllowed)
+        );
+    }
+
+    #[tokio::test]
+    async fn secure() {
+        let headers = [
+            ("Host", "internal"),
+            ("Forwarded", "for=192.0.2.1;host=example.com;proto=https"),
+        ];
+        let response = require(RequireHttpsLayer::reject(), &headers).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get(header::STRICT_TRANSPORT_SECURITY),
+            None
+        );
+        let layer = RequireHttpsLayer::redirect().hsts(Duration::from_secs(31536000), true);
+        let response = require(layer, &headers).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get(header::STRICT_TRANSPORT_SECURITY),
+            Some(&HeaderValue::from_static(
+                "max-age=31536000; includeSubDomains"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn untrusted_https_is_ignored() {
+        // The client says it used HTTPS, in front of the hop our proxy added.
+        let response = require(
+            RequireHttpsLayer::redirect(),
+            &[(
+                "Forwarded",
+                "for=203.0.113.9;host=example.com;proto=https, for=192.0.2.1;host=example.com",
+            )],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+
+        // Or it sent the header straight to us.
+        let trusted = TrustedProxies::networks("192.168.0.0/16".parse().expect("???"));
+        let response = require_with(
+            RequireHttpsLayer::reject(),
+            trusted.header(HopHeader::XForwardedFor),
+            &[
+                ("Host", "example.com"),
+                ("X-Forwarded-For", "192.0.2.1"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn x_forwarded_proto() {
+        let trusted = TrustedProxies::hops(1).header(HopHeader::XForwardedFor);
+        let response = require_with(
+            RequireHttpsLayer::redirect(),
+            trusted.clone(),
+            &[
+                ("Host", "internal"),
+                ("X-Forwarded-For", "192.0.2.1"),
+                ("X-Forwarded-Proto", "http"),
+                ("X-Forwarded-Host", "example.com"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers().get(header::LOCATION),
+            Some(&HeaderValue::from_static("https://example.com/a?b=c"))
+        );
+
+        let response = require_with(
+            RequireHttpsLayer::reject(),
+            trusted,
+            &[
+                ("Host", "example.com"),
+                ("X-Forwarded-For", "192.0.2.1"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn alb() {
+        // An ALB appends to whatever `X-Forwarded-For` the client sent, but sets the protocol
+        // once, for its own listener.
+        let trusted = TrustedProxies::hops(1).header(HopHeader::XForwardedFor);
+        let headers = |proto| {
+            [
+                ("Host", "example.com"),
+                ("X-Forwarded-For", "203.0.113.9, 192.0.2.1"),
+                ("X-Forwarded-Proto", proto),
+            ]
+        };
+        let response = require_with(
+            RequireHttpsLayer::reject(),
+            trusted.clone(),
+            &headers("https"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = require_with(
+            RequireHttpsLayer::redirect(),
+            trusted.clone(),
+            &headers("https"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = require_with(RequireHttpsLayer::redirect(), trusted, &headers("http")).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+}
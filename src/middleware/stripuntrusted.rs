@@ -0,0 +1,386 @@
+//! Takes out the forwarding headers the client could have made up, before anything reads them.
+use crate::Strictness;
+use crate::extract::clientip::{HopFor, HopHeader, TrustedProxies, forwarded_hops, peer_ip};
+use crate::extract::forwarded::{Forwarded, Interface};
+use crate::extract::forwardedinfo::right_aligned;
+use crate::extract::{header_lines, list_header};
+use axum::http::Request;
+use axum::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use axum::http::request::Parts;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+const X_FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO_HEADER: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST_HEADER: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PORT_HEADER: HeaderName = HeaderName::from_static("x-forwarded-port");
+const X_FORWARDED_PREFIX_HEADER: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+
+/// The `X-Forwarded-*` headers with an entry per hop, lined up from the right.
+const X_FORWARDED_HOP_HEADERS: [HeaderName; 4] = [
+    X_FORWARDED_FOR_HEADER,
+    X_FORWARDED_PROTO_HEADER,
+    X_FORWARDED_HOST_HEADER,
+    X_FORWARDED_PORT_HEADER,
+];
+
+#[derive(Clone, Debug)]
+struct Config {
+    trusted: TrustedProxies,
+    truncate: bool,
+}
+
+/// Removes `Forwarded` and `X-Forwarded-*` from requests which didn't come from one of our
+/// [`TrustedProxies`], so handlers and extractors which don't check can't be fooled.
+///
+/// The peer is from [`ConnectInfo<proxy::Addr>`](crate::proxy::Addr) or
+/// [`ConnectInfo<SocketAddr>`](axum::extract::ConnectInfo); if there's neither, it isn't
+/// trusted.
+///
+/// With [`StripUntrustedLayer::truncate`], requests from trusted proxies are cleaned up too:
+/// the hops are cut back to the ones our proxies added, and the header family they don't use is
+/// removed.
+///
+/// Example:
+///
+/// ```rust
+/// use axum::{Router, routing::get};
+/// use axum_proxied::extract::TrustedProxies;
+/// use axum_proxied::middleware::StripUntrustedLayer;
+///
+/// async fn handler() {
+///     todo!()
+/// }
+///
+/// let trusted = TrustedProxies::networks("10.0.0.0/8".parse().unwrap());
+/// let app: Router = Router::new()
+///     .route("/", get(handler))
+///     .layer(StripUntrustedLayer::new(trusted).truncate(true));
+/// ```
+#[derive(Clone, Debug)]
+pub struct StripUntrustedLayer {
+    config: Arc<Config>,
+}
+
+impl StripUntrustedLayer {
+    /// Only believe the headers from `trusted`.
+    pub fn new(trusted: TrustedProxies) -> Self {
+        Self {
+            config: Arc::new(Config {
+                trusted,
+                truncate: false,
+            }),
+        }
+    }
+
+    /// Also cut the hops from trusted proxies back to the ones they added.
+    pub fn truncate(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).truncate = enabled;
+        self
+    }
+}
+
+impl<S> Layer<S> for StripUntrustedLayer {
+    type Service = StripUntrusted<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        StripUntrusted {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// See [`StripUntrustedLayer`].
+#[derive(Clone, Debug)]
+pub struct StripUntrusted<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+impl<S, B> Service<Request<B>> for StripUntrusted<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+        self.config.strip(&mut parts);
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
+
+/// The comma-separated entries of a list header, or `None` if it isn't a string.
+fn entries(headers: &HeaderMap, name: &HeaderName) -> Option<Vec<String>> {
+    let joined = list_header(headers, name).ok()?;
+    Some(
+        joined
+            .map(|joined| {
+                joined
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    )
+}
+
+/// Replace the list header `name` with `entries`, or remove it if there aren't any.
+fn set_entries(headers: &mut HeaderMap, name: HeaderName, entries: &[String]) {
+    headers.remove(&name);
+    if entries.is_empty() {
+        return;
+    }
+    if let Ok(value) = HeaderValue::try_from(entries.join(", ")) {
+        headers.insert(name, value);
+    }
+}
+
+impl Config {
+    fn strip(&self, parts: &mut Parts) {
+        let peer = peer_ip(parts).filter(|peer| self.trusted.is_trusted(*peer, 0));
+        let headers = &mut parts.headers;
+        let Some(peer) = peer else {
+            if cfg!(feature = "tracing") && has_any(headers) {
+                tracing::debug!("removing forwarding headers from an untrusted peer");
+            }
+            headers.remove(header::FORWARDED);
+            for name in X_FORWARDED_HOP_HEADERS {
+                headers.remove(name);
+            }
+            headers.remove(X_FORWARDED_PREFIX_HEADER);
+            return;
+        };
+        if !self.truncate {
+            return;
+        }
+        match self.trusted.hop_header() {
+            HopHeader::Forwarded => {
+                for name in X_FORWARDED_HOP_HEADERS {
+                    headers.remove(name);
+                }
+                self.truncate_forwarded(peer, headers);
+            }
+            HopHeader::XForwardedFor => {
+                headers.remove(header::FORWARDED);
+                self.truncate_x_forwarded(peer, headers);
+            }
+        }
+    }
+
+    fn truncate_forwarded(&self, peer: IpAddr, headers: &mut HeaderMap) {
+        let forwarded = match header_lines(headers, header::FORWARDED) {
+            Ok(Some(lines)) => Forwarded::parse_lines(lines, Strictness::Lenient).ok(),
+            Ok(None) => return,
+            Err(_) => None,
+        };
+        headers.remove(header::FORWARDED);
+        let Some(forwarded) = forwarded else {
+            return;
+        };
+        let forwards = forwarded.forwards();
+        // An invalid hop from a proxy we trust is kept, for whatever reads it to reject.
+        let (_, count) = self.trusted.walk(peer, &forwarded_hops(&forwarded));
+        if count == 0 {
+            return;
+        }
+        let kept = Forwarded::new(forwards[forwards.len() - count..].to_vec());
+        if let Ok(value) = HeaderValue::try_from(&kept) {
+            headers.insert(header::FORWARDED, value);
+        }
+    }
+
+    fn truncate_x_forwarded(&self, peer: IpAddr, headers: &mut HeaderMap) {
+        let lists: Vec<_> = X_FORWARDED_HOP_HEADERS
+            .iter()
+            .map(|name| entries(headers, name).unwrap_or_default())
+            .collect();
+        let len = lists.iter().map(Vec::len).max().unwrap_or(0);
+        let fors: Vec<_> = (0..len)
+            .map(|i| {
+                let interface = right_aligned(&lists[0], len, i)
+                    .and_then(|entry| entry.parse::<Interface>().ok());
+                HopFor::of(interface.as_ref())
+            })
+            .collect();
+        let (_, count) = self.trusted.walk(peer, &fors);
+        for (name, list) in X_FORWARDED_HOP_HEADERS.into_iter().zip(lists) {
+            // The last `count` hops, of which this list only has its last few.
+            let kept = &list[list.len() - count.min(list.len())..];
+            set_entries(headers, name, kept);
+        }
+    }
+}
+
+fn has_any(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::FORWARDED)
+        || headers.contains_key(X_FORWARDED_PREFIX_HEADER)
+        || X_FORWARDED_HOP_HEADERS
+            .iter()
+            .any(|name| headers.contains_key(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tower::{ServiceExt, service_fn};
+
+    async fn strip(
+        layer: StripUntrustedLayer,
+        peer: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> Vec<(String, String)> {
+        let service = layer.layer(service_fn(|request: Request<()>| async move {
+            Ok::<_, Infallible>(request.headers().clone())
+        }));
+        let mut builder = Request::builder();
+        if let Some(peer) = peer {
+            builder = builder.extension(ConnectInfo(peer.parse::<SocketAddr>().expect("???")));
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder.body(()).expect("could not build request");
+        let headers = service.oneshot(request).await.expect("infallible");
+        let mut pairs: Vec<_> = headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    value.to_str().expect("not a string").to_string(),
+                )
+            })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn ten_slash_eight() -> TrustedProxies {
+        TrustedProxies::networks("10.0.0.0/8".parse().expect("could not parse networks"))
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut pairs: Vec<_> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    const ALL: &[(&str, &str)] = &[
+        ("host", "example.com"),
+        ("forwarded", "for=192.0.2.1;host=evil.example"),
+        ("x-forwarded-for", "192.0.2.1"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "evil.example"),
+        ("x-forwarded-port", "443"),
+        ("x-forwarded-prefix", "/evil"),
+    ];
+
+    #[tokio::test]
+    async fn untrusted_peer() {
+        let layer = StripUntrustedLayer::new(ten_slash_eight());
+        let expected = pairs(&[("host", "example.com")]);
+        assert_eq!(
+            strip(layer.clone(), Some("192.0.2.1:1234"), ALL).await,
+            expected
+        );
+        assert_eq!(strip(layer, None, ALL).await, expected);
+    }
+
+    #[tokio::test]
+    async fn trusted_peer() {
+        let layer = StripUntrustedLayer::new(ten_slash_eight());
+        assert_eq!(strip(layer, Some("10.0.0.1:1234"), ALL).await, pairs(ALL));
+    }
+
+    #[tokio::test]
+    async fn truncates_forwarded() {
+        let layer = StripUntrustedLayer::new(ten_slash_eight()).truncate(true);
+        let headers = strip(
+            layer,
+            Some("10.0.0.1:1234"),
+            &[
+                ("forwarded", "for=198.51.100.1;host=evil.example"),
+                ("forwarded", "for=192.0.2.1;host=example.com, for=10.0.0.2"),
+                ("x-forwarded-for", "198.51.100.1"),
+                ("x-forwarded-prefix", "/api"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            headers,
+            pairs(&[
+                ("x-forwarded-prefix", "/api"),
+                ("forwarded", "for=192.0.2.1;host=example.com, for=10.0.0.2"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn truncates_x_forwarded() {
+        let trusted = ten_slash_eight().header(HopHeader::XForwardedFor);
+        let layer = StripUntrustedLayer::new(trusted).truncate(true);
+        let headers = strip(
+            layer,
+            Some("10.0.0.1:1234"),
+            &[
+                ("forwarded", "for=198.51.100.1"),
+                ("x-forwarded-for", "198.51.100.1, 192.0.2.1, 10.0.0.2"),
+                ("x-forwarded-proto", "http, https, https"),
+                ("x-forwarded-host", "example.com"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            headers,
+            pairs(&[
+                ("x-forwarded-for", "192.0.2.1, 10.0.0.2"),
+                ("x-forwarded-proto", "https, https"),
+                ("x-forwarded-host", "example.com"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn truncates_x_forwarded_set_once() {
+        // The proxy appended to what the client sent, but set the rest once, for its own hop.
+        let trusted = ten_slash_eight().header(HopHeader::XForwardedFor);
+        let layer = StripUntrustedLayer::new(trusted).truncate(true);
+        let headers = strip(
+            layer,
+            Some("10.0.0.1:1234"),
+            &[
+                ("x-forwarded-for", "203.0.113.9, 192.0.2.1"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "example.com"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            headers,
+            pairs(&[
+                ("x-forwarded-for", "192.0.2.1"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "example.com"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! IP networks, for saying which addresses belong to proxies we trust.
+//!
+//! ```rust
+//! use axum_proxied::network::NetworkSet;
+//!
+//! let ours: NetworkSet = "10.0.0.0/8, fd00::/8".parse().unwrap();
+//! assert!(ours.contains("10.1.2.3".parse().unwrap()));
+//! assert!(ours.contains("::ffff:10.1.2.3".parse().unwrap()));
+//! assert!(!ours.contains("192.0.2.1".parse().unwrap()));
+//! ```
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// A network we couldn't make sense of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkError {
+    /// The address isn't an IPv4 or IPv6 address.
+    InvalidAddress,
+    /// The prefix length is too long for the address, or the netmask isn't contiguous.
+    InvalidPrefix,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAddress => f.write_str("invalid network address"),
+            Self::InvalidPrefix => f.write_str("invalid network prefix length"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// How many bits an address of this family has.
+fn width(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(u32::from(ip)),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// The first `prefix` bits of a `width` bit address.
+fn mask(width: u8, prefix: u8) -> u128 {
+    if prefix == 0 {
+        return 0;
+    }
+    (u128::MAX >> (128 - width)) & !((1u128 << (width - prefix)) - 1)
+}
+
+/// A network prefix, e.g. `10.0.0.0/8` or `fd00::/8`.
+///
+/// Any bits past the prefix are dropped, so `10.1.2.3/8` is `10.0.0.0/8`. IPv4-mapped IPv6
+/// networks (e.g. `::ffff:10.0.0.0/104`) are kept as the IPv4 network they're mapping, and IPv4
+/// networks contain the mapped forms of their addresses.
+///
+/// Parses from:
+///
+/// * `10.0.0.0/8` and `fd00::/8`;
+/// * `10.0.0.0/255.0.0.0`;
+/// * `[fd00::]/8`; and
+/// * a lone address, e.g. `10.0.0.1` or `[::1]`, as a network of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// The first `prefix` bits of `addr`.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, NetworkError> {
+        if prefix > width(addr) {
+            return Err(NetworkError::InvalidPrefix);
+        }
+        let (addr, prefix) = match addr {
+            IpAddr::V6(v6) if prefix >= 96 => match v6.to_ipv4_mapped() {
+                Some(v4) => (IpAddr::V4(v4), prefix - 96),
+                None => (addr, prefix),
+            },
+            _ => (addr, prefix),
+        };
+        let masked = bits(addr) & mask(width(addr), prefix);
+        let addr = match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(masked as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(masked)),
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// The first address in the network.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// How many leading bits of an address have to match.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` is in the network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match (ip.to_canonical(), self.addr) {
+            // An IPv6 network shorter than /96 may still cover `::ffff:0:0/96`.
+            (IpAddr::V4(v4), IpAddr::V6(_)) => IpAddr::V6(v4.to_ipv6_mapped()),
+            (ip, _) => ip,
+        };
+        if width(ip) != width(self.addr) {
+            return false;
+        }
+        bits(ip) & mask(width(ip), self.prefix) == bits(self.addr)
+    }
+}
+
+impl From<IpAddr> for Network {
+    fn from(value: IpAddr) -> Self {
+        Self::new(value, width(value)).expect("a whole address is always a valid prefix")
+    }
+}
+
+impl FromStr for Network {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .unwrap_or(addr);
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| NetworkError::InvalidAddress)?;
+        let Some(prefix) = prefix else {
+            return Ok(Self::from(addr));
+        };
+        let prefix = match (prefix.parse::<Ipv4Addr>(), addr) {
+            (Ok(netmask), IpAddr::V4(_)) => {
+                let netmask = u32::from(netmask);
+                if netmask.leading_ones() + netmask.trailing_zeros() != 32 {
+                    return Err(NetworkError::InvalidPrefix);
+                }
+                netmask.leading_ones() as u8
+            }
+            _ if prefix.starts_with(|c: char| c.is_ascii_digit()) => prefix
+                .parse::<u8>()
+                .map_err(|_| NetworkError::InvalidPrefix)?,
+            _ => return Err(NetworkError::InvalidPrefix),
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// A set of [`Network`]s, looked up by longest prefix.
+///
+/// Parses from a list of networks, separated by commas and/or whitespace. Shared by the
+/// [PROXY listener](crate::proxy::Listener::trusted) and
+/// [`TrustedProxies`](crate::extract::TrustedProxies).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetworkSet {
+    // The masked bits of each network, by prefix length. Lookups only have to try the prefix
+    // lengths which are actually in use, longest first.
+    v4: BTreeMap<u8, HashSet<u128>>,
+    v6: BTreeMap<u8, HashSet<u128>>,
+}
+
+impl NetworkSet {
+    /// An empty set, which contains nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `network`, returning whether it wasn't already in the set.
+    pub fn insert(&mut self, network: Network) -> bool {
+        let table = match network.addr {
+            IpAddr::V4(_) => &mut self.v4,
+            IpAddr::V6(_) => &mut self.v6,
+        };
+        table
+            .entry(network.prefix)
+            .or_default()
+            .insert(bits(network.addr))
+    }
+
+    /// Whether `ip` is in any of the networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.longest_match(ip).is_some()
+    }
+
+    /// The most specific network `ip` is in.
+    pub fn longest_match(&self, ip: IpAddr) -> Option<Network> {
+        // An IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) is really an IPv4 address.
+        let ip = ip.to_canonical();
+        if let IpAddr::V4(v4) = ip {
+            if let Some(network) = longest_match(&self.v4, ip) {
+                return Some(network);
+            }
+            // Anything that's left is shorter than `::ffff:0:0/96`, so it can't be more specific
+            // than an IPv4 network.
+            return longest_match(&self.v6, IpAddr::V6(v4.to_ipv6_mapped()));
+        }
+        longest_match(&self.v6, ip)
+    }
+
+    /// How many networks there are.
+    pub fn len(&self) -> usize {
+        self.v4
+            .values()
+            .chain(self.v6.values())
+            .map(HashSet::len)
+            .sum()
+    }
+
+    /// Whether there are no networks.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The networks, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = Network> + '_ {
+        let v4 = self.v4.iter().flat_map(|(prefix, networks)| {
+            networks.iter().map(|bits| Network {
+                addr: IpAddr::V4(Ipv4Addr::from(*bits as u32)),
+                prefix: *prefix,
+            })
+        });
+        let v6 = self.v6.iter().flat_map(|(prefix, networks)| {
+            networks.iter().map(|bits| Network {
+                addr: IpAddr::V6(Ipv6Addr::from(*bits)),
+                prefix: *prefix,
+            })
+        });
+        v4.chain(v6)
+    }
+}
+
+fn longest_match(table: &BTreeMap<u8, HashSet<u128>>, ip: IpAddr) -> Option<Network> {
+    table.iter().rev().find_map(|(prefix, networks)| {
+        let masked = bits(ip) & mask(width(ip), *prefix);
+        networks.contains(&masked).then(|| Network {
+            addr: match ip {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(masked as u32)),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(masked)),
+            },
+            prefix: *prefix,
+        })
+    })
+}
+
+impl Extend<Network> for NetworkSet {
+    fn extend<T: IntoIterator<Item = Network>>(&mut self, iter: T) {
+        for network in iter {
+            self.insert(network);
+        }
+    }
+}
+
+impl FromIterator<Network> for NetworkSet {
+    fn from_iter<T: IntoIterator<Item = Network>>(iter: T) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<const N: usize> From<[Network; N]> for NetworkSet {
+    fn from(value: [Network; N]) -> Self {
+        value.into_iter().collect()
+    }
+}
+
+impl FromStr for NetworkSet {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|n| !n.is_empty())
+            .map(Network::from_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse::<IpAddr>().expect("???")
+    }
+
+    fn network(network: &str) -> Network {
+        network.parse::<Network>().expect("could not parse network")
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            network("10.0.0.0/8"),
+            Network::new(ip("10.0.0.0"), 8).unwrap()
+        );
+        assert_eq!(network(" 10.1.2.3/8 "), network("10.0.0.0/8"));
+        assert_eq!(network("10.0.0.0/255.0.0.0"), network("10.0.0.0/8"));
+        assert_eq!(network("10.0.0.1"), network("10.0.0.1/32"));
+        assert_eq!(network("[fd00::]/8"), network("fd00::/8"));
+        assert_eq!(network("[::1]"), network("::1/128"));
+        assert_eq!(network("::ffff:10.0.0.0/104"), network("10.0.0.0/8"));
+        assert_eq!(network("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert_eq!(network("fd12::1/16").to_string(), "fd12::/16");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |s: &str| s.parse::<Network>();
+        assert_eq!(parse("10.0.0/8"), Err(NetworkError::InvalidAddress));
+        assert_eq!(parse("example.com/8"), Err(NetworkError::InvalidAddress));
+        assert_eq!(parse(""), Err(NetworkError::InvalidAddress));
+        assert_eq!(parse("10.0.0.0/33"), Err(NetworkError::InvalidPrefix));
+        assert_eq!(parse("::/129"), Err(NetworkError::InvalidPrefix));
+        assert_eq!(parse("10.0.0.0/"), Err(NetworkError::InvalidPrefix));
+        assert_eq!(parse("10.0.0.0/+8"), Err(NetworkError::InvalidPrefix));
+        assert_eq!(
+            parse("10.0.0.0/255.0.255.0"),
+            Err(NetworkError::InvalidPrefix)
+        );
+        assert_eq!(parse("::/255.0.0.0"), Err(NetworkError::InvalidPrefix));
+    }
+
+    #[test]
+    fn contains() {
+        let ten = network("10.0.0.0/8");
+        assert!(ten.contains(ip("10.255.0.1")));
+        assert!(ten.contains(ip("::ffff:10.255.0.1")));
+        assert!(!ten.contains(ip("11.0.0.1")));
+        assert!(!ten.contains(ip("::a00:1")));
+        assert!(network("0.0.0.0/0").contains(ip("192.0.2.1")));
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(network("::/0").contains(ip("192.0.2.1")));
+        assert!(network("fd00::/8").contains(ip("fdff::1")));
+        assert!(!network("fd00::/8").contains(ip("fe00::1")));
+    }
+
+    #[test]
+    fn longest_match() {
+        let set: NetworkSet = "10.0.0.0/8, 10.1.0.0/16 10.1.2.3\nfd00::/8,::/0"
+            .parse()
+            .expect("could not parse set");
+        assert_eq!(set.len(), 5);
+        assert_eq!(
+            set.longest_match(ip("10.1.2.3")),
+            Some(network("10.1.2.3/32"))
+        );
+        assert_eq!(
+            set.longest_match(ip("10.1.2.4")),
+            Some(network("10.1.0.0/16"))
+        );
+        assert_eq!(
+            set.longest_match(ip("::ffff:10.2.0.1")),
+            Some(network("10.0.0.0/8"))
+        );
+        assert_eq!(set.longest_match(ip("fd00::1")), Some(network("fd00::/8")));
+        assert_eq!(set.longest_match(ip("192.0.2.1")), Some(network("::/0")));
+    }
+
+    #[test]
+    fn set() {
+        let mut set = NetworkSet::new();
+        assert!(set.is_empty());
+        assert!(!set.contains(ip("10.0.0.1")));
+        assert!(set.insert(network("10.0.0.0/8")));
+        assert!(!set.insert(network("10.9.9.9/8")));
+        assert!(set.insert(network("::ffff:172.16.0.0/108")));
+        assert!(set.contains(ip("172.31.0.1")));
+        assert!(!set.contains(ip("172.32.0.1")));
+        let mut networks: Vec<String> = set.iter().map(|n| n.to_string()).collect();
+        networks.sort();
+        assert_eq!(networks, vec!["10.0.0.0/8", "172.16.0.0/12"]);
+        assert_eq!(
+            "10.0.0.0/8, nope".parse::<NetworkSet>(),
+            Err(NetworkError::InvalidAddress)
+        );
+    }
+}
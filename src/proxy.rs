@@ -1,13 +1,54 @@
 //! A listener that speaks the [PROXY][docs] protocol.
 //!
 //! [docs]: https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
+use crate::network::NetworkSet;
 use axum::{extract, serve};
+use std::borrow::Cow;
 use std::net::{Ipv4Addr, SocketAddr};
-use tokio::io::{self, AsyncReadExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio::time;
 
 #[doc(hidden)]
 pub mod parser;
+pub mod stream;
+pub mod udp;
+
+pub use crate::Strictness;
+pub use crate::proxy::stream::Stream;
+pub use crate::proxy::udp::UdpSocket;
+
+/// Something went wrong while reading the PROXY header.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying stream or socket failed.
+    Io(io::Error),
+    /// We read the header, but it didn't make sense.
+    Parse(parser::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(value: parser::Error) -> Self {
+        Self::Parse(value)
+    }
+}
+
+/// What the proxy told us about the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// The connection was relayed on behalf of a client.
+    Proxy,
+    /// The proxy made the connection itself, usually as a health check.
+    Local,
+}
 
 /// The stuff we've parsed from the PROXY Protocol. We don't support Unix domain
 /// sockets.
@@ -15,6 +56,7 @@ pub mod parser;
 pub struct Addr {
     source: SocketAddr,
     destination: SocketAddr,
+    command: Option<Command>,
 }
 
 unsafe impl Send for Addr {}
@@ -24,108 +66,235 @@ impl Addr {
         Self {
             source,
             destination,
+            command: None,
         }
     }
+
+    /// Who initiated the connection.
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    /// Where the connection was headed. `0.0.0.0:0` if we don't know.
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    /// The command from the header, or `None` if the proxy didn't tell us anything.
+    pub fn command(&self) -> Option<Command> {
+        self.command
+    }
+
+    /// Whether the proxy made this connection itself, e.g. to check we're up.
+    pub fn is_local(&self) -> bool {
+        self.command == Some(Command::Local)
+    }
+
+    fn with_command(mut self, command: Command) -> Self {
+        self.command = Some(command);
+        self
+    }
 }
 
 impl From<SocketAddr> for Addr {
     fn from(value: SocketAddr) -> Self {
-        Self {
-            source: value,
-            destination: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
-        }
+        Self::new(value, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
     }
 }
 
 impl From<(SocketAddr, SocketAddr)> for Addr {
     fn from((source, destination): (SocketAddr, SocketAddr)) -> Self {
-        Self {
-            source,
-            destination,
-        }
+        Self::new(source, destination)
     }
 }
 
-/// Probably only use this behind a _trusted_ load balancer. It's susceptible to attackers which
-/// sends data slowly.
+type LocalHandler<IO> = Box<dyn FnMut(Stream<IO>, Addr) + Send>;
+
+/// Headers being read, with the address they came in on.
+type Pending<IO> = JoinSet<(Addr, Result<(Stream<IO>, parser::Where), Error>)>;
+
+/// How long a connection gets to send its header, unless told otherwise.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long, and how much, we'll read off of a `LOCAL` connection we've answered while waiting
+/// for it to hang up.
+const LOCAL_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCAL_DRAIN_LIMIT: u64 = 64 * 1024;
+
+/// Probably only use this behind a _trusted_ load balancer.
+///
+/// Each header is read in its own task, so a connection which sends data slowly doesn't hold up
+/// the others. Connections which don't finish sending one within [`Listener::header_timeout`]
+/// are dropped. There's some mention from the docs (HAProxy) that load balancers will be able to
+/// fit the header and some of the data into a frame, so proxies shouldn't come close.
+///
+/// Wraps any [`serve::Listener`] (a [`TcpListener`] by default), so it works with TLS streams
+/// and the like too. The header is read off with [`Stream`] rather than peeked at.
+///
+/// Connections the proxy makes itself (`LOCAL` in v2) are passed along like any other, with
+/// [`Addr::is_local`] set. Use [`Listener::on_local`] or [`Listener::respond_to_local`] to keep
+/// them away from the application.
 ///
-/// There's some mention from the docs (HAProxy) that load balancers will be able to fit the header
-/// and some of the data into a frame. So, in practise, this may not be a huge deal.
-pub struct Listener {
-    listener: TcpListener,
+/// Anyone can send a header, so use [`Listener::trusted`] to only believe the proxies.
+pub struct Listener<L: serve::Listener = TcpListener> {
+    listener: L,
+    local: Option<LocalHandler<L::Io>>,
+    strictness: Strictness,
+    trusted: Option<NetworkSet>,
+    header_timeout: Duration,
+    pending: Pending<L::Io>,
 }
 
-impl Listener {
-    pub async fn new(listener: TcpListener) -> Self {
-        Self { listener }
+impl<L: serve::Listener> Listener<L> {
+    pub async fn new(listener: L) -> Self {
+        Self::from(listener)
+    }
+
+    /// How closely headers have to follow the spec. [`Strictness::Lenient`] by default; tighten
+    /// it if the proxy is known to get it right.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// How long a connection has to send its header before it's dropped. Five seconds by
+    /// default.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Only read headers from connections from these networks. Anyone else is passed along as
+    /// they are, with their own address; a header from them is left for the application to
+    /// choke on.
+    ///
+    /// ```rust
+    /// use axum_proxied::proxy;
+    ///
+    /// # async fn example() {
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let listener = proxy::Listener::from(listener).trusted("172.16.0.0/12".parse().unwrap());
+    /// # }
+    /// ```
+    pub fn trusted(mut self, networks: NetworkSet) -> Self {
+        self.trusted = Some(networks);
+        self
+    }
+
+    /// Hand `LOCAL` connections to `handler` instead of the application.
+    ///
+    /// The handler is called from within `accept`, so spawn a task if there's any real work to
+    /// do, e.g. serving a separate health check service on the stream.
+    pub fn on_local<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(Stream<L::Io>, Addr) + Send + 'static,
+    {
+        self.local = Some(Box::new(handler));
+        self
+    }
+
+    /// Answer `LOCAL` connections by writing `response` and closing the connection.
+    ///
+    /// Whatever else they send is read and thrown away, for up to five seconds or 64 KiB.
+    ///
+    /// ```rust
+    /// use axum_proxied::proxy;
+    ///
+    /// # async fn example() {
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let listener = proxy::Listener::from(listener)
+    ///     .respond_to_local(&b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"[..]);
+    /// # }
+    /// ```
+    pub fn respond_to_local(self, response: impl Into<Cow<'static, [u8]>>) -> Self {
+        let response = response.into();
+        self.on_local(move |mut stream, _| {
+            let response = response.clone();
+            tokio::spawn(async move {
+                let _ = stream.write_all(&response).await;
+                let _ = stream.shutdown().await;
+                // Closing with unread data could reset the connection before the response is
+                // read, so wait for the other end to hang up. Just not forever.
+                let mut drain = (&mut stream).take(LOCAL_DRAIN_LIMIT);
+                let _ =
+                    time::timeout(LOCAL_DRAIN_TIMEOUT, io::copy(&mut drain, &mut io::sink())).await;
+            });
+        })
     }
 }
 
-impl From<TcpListener> for Listener {
-    fn from(value: TcpListener) -> Self {
-        Self { listener: value }
+impl<L: serve::Listener> From<L> for Listener<L> {
+    fn from(value: L) -> Self {
+        Self {
+            listener: value,
+            local: None,
+            strictness: Strictness::default(),
+            trusted: None,
+            header_timeout: HEADER_TIMEOUT,
+            pending: JoinSet::new(),
+        }
     }
 }
 
-impl serve::Listener for Listener {
-    type Io = TcpStream;
+impl<L> serve::Listener for Listener<L>
+where
+    L: serve::Listener,
+    L::Addr: Into<Addr>,
+{
+    type Io = Stream<L::Io>;
     type Addr = Addr;
 
     async fn accept(&mut self) -> (Self::Io, Self::Addr) {
-        let mut header_buf: Vec<u8> = vec![0; 512];
         loop {
-            let mut stream = match self.listener.accept().await {
-                Ok((stream, _)) => stream,
-                Err(e) => {
-                    if cfg!(feature = "tracing") {
-                        tracing::warn!("error accepting socket {e:?}");
+            let (underlying, read) = tokio::select! {
+                (io, underlying) = self.listener.accept() => {
+                    let underlying: Addr = underlying.into();
+                    if let Some(trusted) = &self.trusted
+                        && !trusted.contains(underlying.source().ip())
+                    {
+                        return (Stream::new(io, vec![]), underlying);
                     }
+                    let (strictness, timeout) = (self.strictness, self.header_timeout);
+                    self.pending.spawn(async move {
+                        let read = time::timeout(timeout, Stream::read_header_with(io, strictness))
+                            .await
+                            .unwrap_or_else(|_| Err(Error::Io(io::ErrorKind::TimedOut.into())));
+                        (underlying, read)
+                    });
                     continue;
                 }
-            };
-            let Ok(read) = stream.peek(&mut header_buf[..]).await else {
-                if cfg!(feature = "tracing") {
-                    tracing::warn!("could not read header");
-                }
-                continue;
-            };
-            let (advance_by, source, destination) = match parser::parse(&header_buf[..read]) {
-                Ok(parser::ParseResult(advance_by, parser::Where::Underlying)) => {
-                    let Ok(source) = stream.peer_addr() else {
+                Some(joined) = self.pending.join_next() => match joined {
+                    Ok(joined) => joined,
+                    Err(e) => {
                         if cfg!(feature = "tracing") {
-                            tracing::warn!("could not read source from peer addr");
+                            tracing::warn!("could not read PROXY information {e:?}");
                         }
                         continue;
-                    };
-                    (
-                        advance_by,
-                        source,
-                        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
-                    )
-                }
-                Ok(parser::ParseResult(
-                    advance_by,
-                    parser::Where::Header {
-                        source,
-                        destination,
-                    },
-                )) => (advance_by, source, destination),
+                    }
+                },
+            };
+            let (stream, header) = match read {
+                Ok(read) => read,
                 Err(e) => {
                     if cfg!(feature = "tracing") {
-                        tracing::warn!("could not parse PROXY information {e:?}");
+                        tracing::warn!("could not read PROXY information {e:?}");
                     }
                     continue;
                 }
             };
-            // First, try advancing the stream.
-            let Ok(read) = stream.read(&mut header_buf[..advance_by]).await else {
-                if cfg!(feature = "tracing") {
-                    tracing::warn!("could not read from stream");
-                }
-                continue;
+            let addr = match header {
+                parser::Where::Underlying => underlying,
+                parser::Where::Local => underlying.with_command(Command::Local),
+                parser::Where::Header {
+                    source,
+                    destination,
+                } => Addr::new(source, destination).with_command(Command::Proxy),
             };
-            assert_eq!(read, advance_by);
-            return (stream, Addr::new(source, destination));
+            if let (true, Some(handler)) = (addr.is_local(), self.local.as_mut()) {
+                handler(stream, addr);
+                continue;
+            }
+            return (stream, addr);
         }
     }
 
@@ -135,8 +304,145 @@ impl serve::Listener for Listener {
     }
 }
 
-impl extract::connect_info::Connected<serve::IncomingStream<'_, Listener>> for Addr {
-    fn connect_info(stream: serve::IncomingStream<'_, Listener>) -> Self {
+impl<L> extract::connect_info::Connected<serve::IncomingStream<'_, Listener<L>>> for Addr
+where
+    L: serve::Listener,
+    L::Addr: Into<Addr>,
+{
+    fn connect_info(stream: serve::IncomingStream<'_, Listener<L>>) -> Self {
         stream.remote_addr().clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::serve::Listener as _;
+    use tokio::net::TcpStream;
+
+    const V2_LOCAL: &[u8] = &[
+        0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A, 0x20, 0x00, 0x00,
+        0x00,
+    ];
+
+    #[tokio::test]
+    async fn local_is_reported() {
+        let mut listener =
+            Listener::new(TcpListener::bind("127.0.0.1:0").await.expect("bind")).await;
+        let local_addr = listener.local_addr().expect("no local addr");
+        let mut client = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        client.write_all(V2_LOCAL).await.expect("could not write");
+        let (_, addr) = listener.accept().await;
+        assert!(addr.is_local());
+        assert_eq!(addr.source(), client.local_addr().expect("no addr"));
+    }
+
+    #[tokio::test]
+    async fn untrusted_header_is_ignored() {
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").await.expect("bind"))
+            .await
+            .trusted("10.0.0.0/8".parse().expect("could not parse networks"));
+        let local_addr = listener.local_addr().expect("no local addr");
+        let mut client = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        let header = b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n";
+        client.write_all(header).await.expect("could not write");
+        let (mut stream, addr) = listener.accept().await;
+        assert_eq!(addr.source(), client.local_addr().expect("no addr"));
+        assert_eq!(addr.command(), None);
+        let mut read = vec![0; header.len()];
+        stream.read_exact(&mut read).await.expect("could not read");
+        assert_eq!(read, header);
+    }
+
+    #[tokio::test]
+    async fn slow_header_does_not_block() {
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").await.expect("bind"))
+            .await
+            .header_timeout(Duration::from_millis(100));
+        let local_addr = listener.local_addr().expect("no local addr");
+
+        let mut slow = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        slow.write_all(b"PROXY TCP4 ")
+            .await
+            .expect("could not write");
+        let mut client = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        client
+            .write_all(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n")
+            .await
+            .expect("could not write");
+        let (_, addr) = listener.accept().await;
+        assert_eq!(
+            addr.source(),
+            "10.0.0.1:1234".parse::<SocketAddr>().expect("???")
+        );
+
+        let accepting = tokio::spawn(async move { listener.accept().await });
+        let mut rest = vec![];
+        let read = slow.read_to_end(&mut rest).await;
+        assert!(read.is_err() || rest.is_empty());
+        accepting.abort();
+    }
+
+    #[tokio::test]
+    async fn local_is_answered() {
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").await.expect("bind"))
+            .await
+            .respond_to_local(&b"OK"[..]);
+        let local_addr = listener.local_addr().expect("no local addr");
+        let accepting = tokio::spawn(async move { listener.accept().await.1 });
+
+        let mut health = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        health.write_all(V2_LOCAL).await.expect("could not write");
+        health.shutdown().await.expect("could not shut down");
+        let mut response = vec![];
+        health
+            .read_to_end(&mut response)
+            .await
+            .expect("could not read");
+        assert_eq!(response, b"OK");
+
+        let mut client = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        client
+            .write_all(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n")
+            .await
+            .expect("could not write");
+        let addr = accepting.await.expect("could not join");
+        assert_eq!(addr.command(), Some(Command::Proxy));
+        assert_eq!(
+            addr.source(),
+            "10.0.0.1:1234".parse::<SocketAddr>().expect("???")
+        );
+    }
+
+    #[tokio::test]
+    async fn local_drain_is_bounded() {
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").await.expect("bind"))
+            .await
+            .respond_to_local(&b"OK"[..]);
+        let local_addr = listener.local_addr().expect("no local addr");
+        let accepting = tokio::spawn(async move { listener.accept().await });
+
+        let mut health = TcpStream::connect(local_addr.source())
+            .await
+            .expect("could not connect");
+        health.write_all(V2_LOCAL).await.expect("could not write");
+        let chunk = vec![0; 16 * 1024];
+        let flooding = async { while health.write_all(&chunk).await.is_ok() {} };
+        time::timeout(Duration::from_secs(3), flooding)
+            .await
+            .expect("drain was not bounded");
+        accepting.abort();
+    }
+}
@@ -4,6 +4,7 @@
 //!
 //! [proxy]: https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
 
+use crate::Strictness;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Range;
 
@@ -15,6 +16,18 @@ pub enum Error {
     UnsupportedCommand(u8),
     UnsupportedFamily(u8),
     UnsupportedProtocol(u8),
+    /// No `\r\n` within the 107 bytes a v1 header is allowed.
+    HeaderTooLong,
+    /// A v1 protocol other than `TCP4`, `TCP6` or `UNKNOWN`.
+    UnsupportedInetProtocol,
+    /// A v1 address which doesn't match the declared protocol.
+    AddressFamilyMismatch,
+    /// A v1 port which isn't plain decimal, e.g. `080`.
+    InvalidPort,
+    /// Something after the destination port in a v1 header.
+    TrailingFields,
+    /// A v2 header whose length is too short to fit the addresses for its family.
+    InvalidAddressLength,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -24,6 +37,9 @@ pub enum Where {
         destination: SocketAddr,
     },
     Underlying,
+    /// The proxy made this connection itself (e.g. a health check), so the address block should
+    /// be ignored.
+    Local,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -81,6 +97,12 @@ fn is_proxy_protocol(buf: &[u8]) -> Option<Version> {
     None
 }
 
+/// Too short to tell yet, but it could still turn into a header.
+fn is_partial_proxy_protocol(buf: &[u8]) -> bool {
+    (buf.len() < PROXY_V1_MAGIC.len() && PROXY_V1_MAGIC.starts_with(buf))
+        || (buf.len() < PROXY_V2_MAGIC.len() && PROXY_V2_MAGIC.starts_with(buf))
+}
+
 impl From<std::str::Utf8Error> for Error {
     fn from(_: std::str::Utf8Error) -> Self {
         Self::InvalidFormat
@@ -114,25 +136,59 @@ macro_rules! try_parse_v1_addr {
     };
 }
 
-fn parse_v1(buf: &[u8]) -> Result<ParseResult, Error> {
-    let Some(contents_end) = buf[..]
+/// The longest a v1 header can be, including the `\r\n`.
+const PROXY_V1_MAX_LENGTH: usize = 107;
+const PROXY_V1_TCP4_PROTO: &[u8] = b"TCP4";
+const PROXY_V1_TCP6_PROTO: &[u8] = b"TCP6";
+
+/// Ports are plain decimal, without leading zeros.
+fn parse_v1_port_strict(field: Option<&[u8]>) -> Result<u16, Error> {
+    let field = field.ok_or(Error::ShortHeader)?;
+    let plain = !field.is_empty()
+        && field.iter().all(u8::is_ascii_digit)
+        && (field.len() == 1 || field[0] != b'0');
+    if !plain {
+        return Err(Error::InvalidPort);
+    }
+    std::str::from_utf8(field)?
+        .parse::<u16>()
+        .map_err(|_| Error::InvalidPort)
+}
+
+fn parse_v1(buf: &[u8], strictness: Strictness) -> Result<ParseResult, Error> {
+    let search = match strictness {
+        Strictness::Strict => &buf[..buf.len().min(PROXY_V1_MAX_LENGTH)],
+        Strictness::Lenient => buf,
+    };
+    let Some(contents_end) = search
         .windows(PROXY_V1_DELIMITER.len())
         .position(|w| w == PROXY_V1_DELIMITER)
     else {
+        if search.len() == PROXY_V1_MAX_LENGTH && strictness == Strictness::Strict {
+            return Err(Error::HeaderTooLong);
+        }
         return Err(Error::ShortHeader);
     };
     let header_end = contents_end + PROXY_V1_DELIMITER.len();
     let mut fields = buf[PROXY_V1_MAGIC.len()..contents_end].split(|x| *x == b' ');
     let inet_proto = fields.next().ok_or(Error::ShortHeader)?;
     if inet_proto == PROXY_V1_UNKNOWN_PROTO {
+        // Anything else on the line is to be ignored.
         return Ok(ParseResult(header_end, Where::Underlying));
     }
-    let ip_source = try_parse_v1_addr!(fields, IpAddr)?;
-    let ip_destination = try_parse_v1_addr!(fields, IpAddr)?;
-    let port_source = try_parse_v1_addr!(fields, u16)?;
-    let port_destination = try_parse_v1_addr!(fields, u16)?;
-    let source = SocketAddr::from((ip_source, port_source));
-    let destination = SocketAddr::from((ip_destination, port_destination));
+    let (source, destination) = match strictness {
+        Strictness::Strict => parse_v1_addresses_strict(inet_proto, fields)?,
+        Strictness::Lenient => {
+            let ip_source = try_parse_v1_addr!(fields, IpAddr)?;
+            let ip_destination = try_parse_v1_addr!(fields, IpAddr)?;
+            let port_source = try_parse_v1_addr!(fields, u16)?;
+            let port_destination = try_parse_v1_addr!(fields, u16)?;
+            (
+                SocketAddr::from((ip_source, port_source)),
+                SocketAddr::from((ip_destination, port_destination)),
+            )
+        }
+    };
     Ok(ParseResult(
         header_end,
         Where::Header {
@@ -142,10 +198,43 @@ fn parse_v1(buf: &[u8]) -> Result<ParseResult, Error> {
     ))
 }
 
+fn parse_v1_addresses_strict<'a>(
+    inet_proto: &[u8],
+    mut fields: impl Iterator<Item = &'a [u8]>,
+) -> Result<(SocketAddr, SocketAddr), Error> {
+    let ipv6 = match inet_proto {
+        PROXY_V1_TCP4_PROTO => false,
+        PROXY_V1_TCP6_PROTO => true,
+        _ => return Err(Error::UnsupportedInetProtocol),
+    };
+    let ip_source = parse_v1_ip_strict(fields.next(), ipv6)?;
+    let ip_destination = parse_v1_ip_strict(fields.next(), ipv6)?;
+    let port_source = parse_v1_port_strict(fields.next())?;
+    let port_destination = parse_v1_port_strict(fields.next())?;
+    if fields.next().is_some() {
+        return Err(Error::TrailingFields);
+    }
+    Ok((
+        SocketAddr::from((ip_source, port_source)),
+        SocketAddr::from((ip_destination, port_destination)),
+    ))
+}
+
+fn parse_v1_ip_strict(field: Option<&[u8]>, ipv6: bool) -> Result<IpAddr, Error> {
+    let field = field.ok_or(Error::ShortHeader)?;
+    let ip = std::str::from_utf8(field)?.parse::<IpAddr>()?;
+    if ip.is_ipv6() != ipv6 {
+        return Err(Error::AddressFamilyMismatch);
+    }
+    Ok(ip)
+}
+
 // From the spec.
 const PROXY_V2_VERSION_COMMAND_INDEX: usize = 12;
 const PROXY_V2_FAMILY_PROTO_INDEX: usize = 13;
 const PROXY_V2_LENGTH_RANGE: Range<usize> = 14..16;
+const PROXY_V2_INET_LENGTH: usize = 12;
+const PROXY_V2_INET6_LENGTH: usize = 36;
 
 impl From<u8> for Version {
     fn from(value: u8) -> Self {
@@ -226,7 +315,7 @@ fn parse_v2_inet(buf: &[u8]) -> Result<(usize, SocketAddr, SocketAddr), Error> {
     let source_port: u16 = try_parse_u16!(buf.get(8..10))?;
     let destination_port: u16 = try_parse_u16!(buf.get(10..12))?;
     Ok((
-        12,
+        PROXY_V2_INET_LENGTH,
         SocketAddr::from((source_addr, source_port)),
         SocketAddr::from((destination_addr, destination_port)),
     ))
@@ -238,7 +327,7 @@ fn parse_v2_inet6(buf: &[u8]) -> Result<(usize, SocketAddr, SocketAddr), Error>
     let source_port: u16 = try_parse_u16!(buf.get(32..34))?;
     let destination_port: u16 = try_parse_u16!(buf.get(34..36))?;
     Ok((
-        36,
+        PROXY_V2_INET6_LENGTH,
         SocketAddr::from((source_addr, source_port)),
         SocketAddr::from((destination_addr, destination_port)),
     ))
@@ -253,12 +342,16 @@ fn parse_v2(buf: &[u8]) -> Result<ParseResult, Error> {
         .get(PROXY_V2_FAMILY_PROTO_INDEX)
         .ok_or(Error::ShortHeader)
         .map(|fp| family_protocol_from_u8(*fp))?;
-    let length = try_parse_u16!(buf.get(PROXY_V2_LENGTH_RANGE)).map(usize::from)?;
+    let length = buf
+        .get(PROXY_V2_LENGTH_RANGE)
+        .ok_or(Error::ShortHeader)
+        .map(|l| usize::from(u16::from_be_bytes([l[0], l[1]])))?;
     let contents_start = PROXY_V2_LENGTH_RANGE.end;
     let header_end = PROXY_V2_LENGTH_RANGE.end + length;
-    if buf.len() < header_end {
+    // Only ever look at what the header says belongs to it, anything past that is the payload.
+    let Some(contents) = buf.get(contents_start..header_end) else {
         return Err(Error::ShortHeader);
-    }
+    };
     match version {
         Version::V2 => {}
         Version::V1 => {
@@ -272,6 +365,10 @@ fn parse_v2(buf: &[u8]) -> Result<ParseResult, Error> {
         (Command::Other(c), _, _) => {
             return Err(Error::UnsupportedCommand(c));
         }
+        // The spec says to ignore the family and protocol, and to use the real endpoints.
+        (Command::Local, _, _) => {
+            return Ok(ParseResult(header_end, Where::Local));
+        }
         (_, Family::Other(f), _) => {
             return Err(Error::UnsupportedFamily(f));
         }
@@ -288,10 +385,17 @@ fn parse_v2(buf: &[u8]) -> Result<ParseResult, Error> {
         (_, _, Protocol::Unspecified) => {
             return Ok(ParseResult(header_end, Where::Underlying));
         }
-        (_, Family::Inet, _) => parse_v2_inet(&buf[contents_start..])?,
-        (_, Family::Inet6, _) => parse_v2_inet6(&buf[contents_start..])?,
+        (_, Family::Inet, _) if contents.len() < PROXY_V2_INET_LENGTH => {
+            return Err(Error::InvalidAddressLength);
+        }
+        (_, Family::Inet6, _) if contents.len() < PROXY_V2_INET6_LENGTH => {
+            return Err(Error::InvalidAddressLength);
+        }
+        (_, Family::Inet, _) => parse_v2_inet(contents)?,
+        (_, Family::Inet6, _) => parse_v2_inet6(contents)?,
     };
-    // In the future, consider using _addresses_length to parse the extended headers.
+    // Anything after the addresses are TLVs, which we skip over for now. In the future, consider
+    // using _addresses_length to parse them.
 
     Ok(ParseResult(
         header_end,
@@ -303,10 +407,300 @@ fn parse_v2(buf: &[u8]) -> Result<ParseResult, Error> {
 }
 
 pub fn parse(buf: &[u8]) -> Result<ParseResult, Error> {
+    parse_with(buf, Strictness::default())
+}
+
+pub fn parse_with(buf: &[u8], strictness: Strictness) -> Result<ParseResult, Error> {
     match is_proxy_protocol(buf) {
-        Some(Version::V1) => parse_v1(buf),
+        Some(Version::V1) => parse_v1(buf, strictness),
         Some(Version::V2) => parse_v2(buf),
         Some(Version::Other(v)) => Err(Error::UnsupportedVersion(v)),
+        None if is_partial_proxy_protocol(buf) => Err(Error::ShortHeader),
         None => Ok(ParseResult(0, Where::Underlying)),
     }
 }
+
+/// Like [`parse`], but for a whole datagram. Only v2 can be sent over UDP, and only with a
+/// datagram protocol. Anything which merely starts like a header has all the bytes it'll ever
+/// have, so it's passed along as a payload.
+pub fn parse_datagram(buf: &[u8]) -> Result<ParseResult, Error> {
+    match is_proxy_protocol(buf) {
+        Some(Version::V2) => {}
+        Some(Version::V1) => return Err(Error::UnsupportedVersion(1)),
+        Some(Version::Other(v)) => return Err(Error::UnsupportedVersion(v)),
+        None => return Ok(ParseResult(0, Where::Underlying)),
+    }
+    let parsed = parse_v2(buf)?;
+    let (_, protocol) = family_protocol_from_u8(buf[PROXY_V2_FAMILY_PROTO_INDEX]);
+    match (&parsed.1, protocol) {
+        (Where::Header { .. }, Protocol::Stream) => Err(Error::UnsupportedProtocol(1)),
+        _ => Ok(parsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn v2(command: u8, family_protocol: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut buf = PROXY_V2_MAGIC.to_vec();
+        buf.push(0x20 | command);
+        buf.push(family_protocol);
+        buf.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        buf.extend_from_slice(addresses);
+        buf
+    }
+
+    fn v1(line: &str) -> Result<ParseResult, Error> {
+        parse_with(line.as_bytes(), Strictness::Strict)
+    }
+
+    fn v1_lenient(line: &str) -> Result<ParseResult, Error> {
+        parse_with(line.as_bytes(), Strictness::Lenient)
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let line = "PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n";
+        assert_eq!(
+            v1(line),
+            Ok(ParseResult(
+                line.len(),
+                Where::Header {
+                    source: "192.168.0.1:56324".parse::<SocketAddr>().expect("???"),
+                    destination: "192.168.0.11:443".parse::<SocketAddr>().expect("???"),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let line = "PROXY TCP6 2001:db8::1 2001:db8::2 1 65535\r\n";
+        assert_eq!(
+            v1(line),
+            Ok(ParseResult(
+                line.len(),
+                Where::Header {
+                    source: "[2001:db8::1]:1".parse::<SocketAddr>().expect("???"),
+                    destination: "[2001:db8::2]:65535".parse::<SocketAddr>().expect("???"),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn v1_unknown_ignores_the_rest() {
+        let line = "PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n";
+        assert_eq!(v1(line), Ok(ParseResult(line.len(), Where::Underlying)));
+    }
+
+    #[test]
+    fn v1_family_mismatch() {
+        let line = "PROXY TCP4 2001:db8::1 2001:db8::2 1 2\r\n";
+        assert_eq!(v1(line), Err(Error::AddressFamilyMismatch));
+        assert!(v1_lenient(line).is_ok());
+        let line = "PROXY TCP6 10.0.0.1 10.0.0.2 1 2\r\n";
+        assert_eq!(v1(line), Err(Error::AddressFamilyMismatch));
+    }
+
+    #[test]
+    fn v1_unsupported_protocol() {
+        let line = "PROXY UDP4 10.0.0.1 10.0.0.2 1 2\r\n";
+        assert_eq!(v1(line), Err(Error::UnsupportedInetProtocol));
+        assert!(v1_lenient(line).is_ok());
+    }
+
+    #[test]
+    fn v1_leading_zeros() {
+        let line = "PROXY TCP4 10.0.0.1 10.0.0.2 01 2\r\n";
+        assert_eq!(v1(line), Err(Error::InvalidPort));
+        assert!(v1_lenient(line).is_ok());
+        let line = "PROXY TCP4 10.0.0.1 10.0.0.2 +1 2\r\n";
+        assert_eq!(v1(line), Err(Error::InvalidPort));
+    }
+
+    #[test]
+    fn v1_trailing_fields() {
+        let line = "PROXY TCP4 10.0.0.1 10.0.0.2 1 2 3\r\n";
+        assert_eq!(v1(line), Err(Error::TrailingFields));
+        assert!(v1_lenient(line).is_ok());
+    }
+
+    #[test]
+    fn v1_lenient_by_default() {
+        let line = "PROXY TCP4 10.0.0.1 10.0.0.2 01 2 3\r\n";
+        assert_eq!(parse(line.as_bytes()), v1_lenient(line));
+    }
+
+    #[test]
+    fn v1_too_long() {
+        let line = format!("PROXY UNKNOWN {}\r\n", "a".repeat(100));
+        assert_eq!(v1(&line), Err(Error::HeaderTooLong));
+        assert!(v1_lenient(&line).is_ok());
+        // Not there yet, but it could be.
+        assert_eq!(v1(&line[..50]), Err(Error::ShortHeader));
+    }
+
+    #[test]
+    fn v1_longest() {
+        let line = "PROXY UNKNOWN ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff 65535 65535\r\n";
+        assert_eq!(line.len(), PROXY_V1_MAX_LENGTH);
+        assert!(v1(line).is_ok());
+    }
+
+    const INET_ADDRESSES: &[u8] = &[
+        192, 168, 0, 1, // source
+        192, 168, 0, 11, // destination
+        0xDC, 0x04, // 56324
+        0x01, 0xBB, // 443
+    ];
+
+    fn inet6_addresses() -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().expect("???").octets());
+        buf.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().expect("???").octets());
+        buf.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        buf
+    }
+
+    fn inet_where() -> Where {
+        Where::Header {
+            source: "192.168.0.1:56324".parse::<SocketAddr>().expect("???"),
+            destination: "192.168.0.11:443".parse::<SocketAddr>().expect("???"),
+        }
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let buf = v2(0x1, 0x11, INET_ADDRESSES);
+        assert_eq!(parse(&buf), Ok(ParseResult(28, inet_where())));
+    }
+
+    #[test]
+    fn v2_udp4() {
+        let buf = v2(0x1, 0x12, INET_ADDRESSES);
+        assert_eq!(parse(&buf), Ok(ParseResult(28, inet_where())));
+    }
+
+    #[test]
+    fn v2_tcp6() {
+        let buf = v2(0x1, 0x21, &inet6_addresses());
+        assert_eq!(
+            parse(&buf),
+            Ok(ParseResult(
+                52,
+                Where::Header {
+                    source: "[2001:db8::1]:56324".parse::<SocketAddr>().expect("???"),
+                    destination: "[2001:db8::2]:443".parse::<SocketAddr>().expect("???"),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn v2_skips_tlvs() {
+        let mut addresses = INET_ADDRESSES.to_vec();
+        // PP2_TYPE_NOOP, with 3 bytes of padding.
+        addresses.extend_from_slice(&[0x04, 0x00, 0x03, 0x00, 0x00, 0x00]);
+        let buf = v2(0x1, 0x11, &addresses);
+        assert_eq!(parse(&buf), Ok(ParseResult(34, inet_where())));
+    }
+
+    #[test]
+    fn v2_leaves_payload_alone() {
+        let mut buf = v2(0x1, 0x11, INET_ADDRESSES);
+        buf.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        assert_eq!(parse(&buf), Ok(ParseResult(28, inet_where())));
+    }
+
+    #[test]
+    fn v2_length_too_short_for_inet() {
+        // The declared length stops short of the addresses, even though the payload would fill
+        // in the rest.
+        let mut buf = v2(0x1, 0x11, &INET_ADDRESSES[..8]);
+        buf.extend_from_slice(&INET_ADDRESSES[8..]);
+        assert_eq!(parse(&buf), Err(Error::InvalidAddressLength));
+    }
+
+    #[test]
+    fn v2_length_too_short_for_inet6() {
+        let addresses = inet6_addresses();
+        let mut buf = v2(0x1, 0x21, &addresses[..12]);
+        buf.extend_from_slice(&addresses[12..]);
+        assert_eq!(parse(&buf), Err(Error::InvalidAddressLength));
+    }
+
+    #[test]
+    fn v2_short() {
+        let buf = v2(0x1, 0x11, INET_ADDRESSES);
+        for end in 0..buf.len() {
+            assert_eq!(parse(&buf[..end]), Err(Error::ShortHeader), "at {end}");
+        }
+    }
+
+    #[test]
+    fn v2_unsupported() {
+        let mut buf = v2(0x1, 0x11, INET_ADDRESSES);
+        buf[12] = 0x31;
+        assert_eq!(parse(&buf), Err(Error::UnsupportedVersion(3)));
+        let buf = v2(0x2, 0x11, INET_ADDRESSES);
+        assert_eq!(parse(&buf), Err(Error::UnsupportedCommand(2)));
+        let buf = v2(0x1, 0x41, INET_ADDRESSES);
+        assert_eq!(parse(&buf), Err(Error::UnsupportedFamily(4)));
+        let buf = v2(0x1, 0x31, &[0; 216]);
+        assert_eq!(parse(&buf), Err(Error::UnsupportedFamily(3)));
+        let buf = v2(0x1, 0x13, INET_ADDRESSES);
+        assert_eq!(parse(&buf), Err(Error::UnsupportedProtocol(3)));
+    }
+
+    #[test]
+    fn v2_local() {
+        let buf = v2(0x0, 0x00, &[]);
+        assert_eq!(parse(&buf), Ok(ParseResult(16, Where::Local)));
+    }
+
+    #[test]
+    fn v2_local_ignores_addresses() {
+        let buf = v2(0x0, 0x11, &[127, 0, 0, 1, 127, 0, 0, 2, 0, 1, 0, 2]);
+        assert_eq!(parse(&buf), Ok(ParseResult(28, Where::Local)));
+    }
+
+    #[test]
+    fn v2_proxy_unspecified() {
+        let buf = v2(0x1, 0x00, &[]);
+        assert_eq!(parse(&buf), Ok(ParseResult(16, Where::Underlying)));
+    }
+
+    #[test]
+    fn datagram_v1() {
+        let line = "PROXY TCP4 10.0.0.1 10.0.0.2 1 2\r\n";
+        assert_eq!(
+            parse_datagram(line.as_bytes()),
+            Err(Error::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn datagram_v2() {
+        let buf = v2(0x1, 0x12, INET_ADDRESSES);
+        assert_eq!(parse_datagram(&buf), Ok(ParseResult(28, inet_where())));
+        let buf = v2(0x1, 0x11, INET_ADDRESSES);
+        assert_eq!(parse_datagram(&buf), Err(Error::UnsupportedProtocol(1)));
+        let buf = v2(0x0, 0x11, INET_ADDRESSES);
+        assert_eq!(parse_datagram(&buf), Ok(ParseResult(28, Where::Local)));
+    }
+
+    #[test]
+    fn datagram_partial_magic() {
+        for payload in [&PROXY_V2_MAGIC[..4], &b"PROX"[..]] {
+            assert_eq!(
+                parse_datagram(payload),
+                Ok(ParseResult(0, Where::Underlying))
+            );
+        }
+        let buf = v2(0x1, 0x12, INET_ADDRESSES);
+        assert_eq!(parse_datagram(&buf[..20]), Err(Error::ShortHeader));
+    }
+}
@@ -0,0 +1,257 @@
+//! A stream wrapper which reads the PROXY header off of any async I/O type.
+//!
+//! [`tokio::net::TcpStream::peek`] only works on actual sockets, so instead we read the header
+//! normally and hand back whatever we read past it before touching the underlying stream again.
+use crate::proxy::{Error, Strictness, parser};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+/// How much we try to read at a time while looking for the header.
+const READ_CHUNK: usize = 512;
+
+/// The largest header we're willing to buffer: the v2 fixed header, plus the largest length it
+/// can declare.
+const MAX_HEADER_LENGTH: usize = 16 + u16::MAX as usize;
+
+/// An async I/O type with the PROXY header stripped off.
+///
+/// Anything we read past the end of the header is replayed to readers before reading from the
+/// underlying stream. Writes go straight through.
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::proxy::Stream;
+///
+/// # async fn example() {
+/// let (mut client, server) = tokio::io::duplex(64);
+/// # use tokio::io::AsyncWriteExt;
+/// client.write_all(b"PROXY UNKNOWN\r\nhello").await.unwrap();
+/// let (stream, _header) = Stream::read_header(server).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Stream<IO> {
+    inner: IO,
+    prefix: Vec<u8>,
+    position: usize,
+}
+
+impl<IO> Stream<IO> {
+    /// Wrap `inner`, replaying `prefix` before reading from it.
+    pub fn new(inner: IO, prefix: Vec<u8>) -> Self {
+        Self {
+            inner,
+            prefix,
+            position: 0,
+        }
+    }
+
+    /// The underlying stream.
+    pub fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+
+    /// The underlying stream. Reading from it directly skips whatever is left of the prefix.
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.inner
+    }
+
+    /// Bytes we've buffered, but nobody has read yet.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix[self.position..]
+    }
+
+    /// The underlying stream, and whatever is left of the prefix.
+    pub fn into_inner(mut self) -> (IO, Vec<u8>) {
+        self.prefix.drain(..self.position);
+        (self.inner, self.prefix)
+    }
+}
+
+impl<IO> Stream<IO>
+where
+    IO: AsyncRead + Unpin,
+{
+    /// Read the PROXY header off of `inner`, if there is one.
+    ///
+    /// Connections without a header come back as [`parser::Where::Underlying`], with everything
+    /// we've read so far left in the prefix.
+    pub async fn read_header(inner: IO) -> Result<(Self, parser::Where), Error> {
+        Self::read_header_with(inner, Strictness::default()).await
+    }
+
+    /// Like [`Stream::read_header`], but with a choice of how closely to follow the spec.
+    pub async fn read_header_with(
+        mut inner: IO,
+        strictness: Strictness,
+    ) -> Result<(Self, parser::Where), Error> {
+        let mut buf: Vec<u8> = Vec::with_capacity(READ_CHUNK);
+        loop {
+            buf.reserve(READ_CHUNK);
+            let read = inner.read_buf(&mut buf).await?;
+            match parser::parse_with(&buf[..], strictness) {
+                Err(parser::Error::ShortHeader) if read != 0 && buf.len() < MAX_HEADER_LENGTH => {
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+                Ok(parser::ParseResult(advance_by, header)) => {
+                    let prefix = buf.split_off(advance_by);
+                    return Ok((Self::new(inner, prefix), header));
+                }
+            }
+        }
+    }
+}
+
+impl<IO> AsyncRead for Stream<IO>
+where
+    IO: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let remaining = &this.prefix[this.position..];
+        if remaining.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let amount = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..amount]);
+        this.position += amount;
+        if this.position == this.prefix.len() {
+            // Don't hang on to the memory once it's all been read.
+            this.prefix = Vec::new();
+            this.position = 0;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO> AsyncWrite for Stream<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::io::AsyncWriteExt;
+
+    async fn read_all<IO: AsyncRead + Unpin>(stream: &mut Stream<IO>) -> Vec<u8> {
+        let mut out = vec![];
+        stream.read_to_end(&mut out).await.expect("could not read");
+        out
+    }
+
+    #[tokio::test]
+    async fn v1_replays_payload() {
+        let (mut client, server) = io::duplex(1024);
+        client
+            .write_all(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n")
+            .await
+            .expect("could not write");
+        drop(client);
+        let (mut stream, header) = Stream::read_header(server)
+            .await
+            .expect("could not read header");
+        assert_eq!(
+            header,
+            parser::Where::Header {
+                source: "192.168.0.1:56324".parse::<SocketAddr>().expect("???"),
+                destination: "192.168.0.11:443".parse::<SocketAddr>().expect("???"),
+            }
+        );
+        assert_eq!(read_all(&mut stream).await, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn no_header_replays_everything() {
+        let (mut client, server) = io::duplex(1024);
+        client
+            .write_all(b"GET / HTTP/1.1\r\n")
+            .await
+            .expect("could not write");
+        drop(client);
+        let (mut stream, header) = Stream::read_header(server)
+            .await
+            .expect("could not read header");
+        assert_eq!(header, parser::Where::Underlying);
+        assert_eq!(read_all(&mut stream).await, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn header_split_across_writes() {
+        let (mut client, server) = io::duplex(1024);
+        let reader = tokio::spawn(Stream::read_header(server));
+        for part in [
+            &b"PRO"[..],
+            b"XY TCP4 10.0.0.1 10.0.0.2 1",
+            b"234 80\r",
+            b"\nhi",
+        ] {
+            client.write_all(part).await.expect("could not write");
+            tokio::task::yield_now().await;
+        }
+        drop(client);
+        let (mut stream, header) = reader
+            .await
+            .expect("could not join")
+            .expect("could not read header");
+        assert_eq!(
+            header,
+            parser::Where::Header {
+                source: "10.0.0.1:1234".parse::<SocketAddr>().expect("???"),
+                destination: "10.0.0.2:80".parse::<SocketAddr>().expect("???"),
+            }
+        );
+        assert_eq!(read_all(&mut stream).await, b"hi");
+    }
+
+    #[tokio::test]
+    async fn truncated_header() {
+        let (mut client, server) = io::duplex(1024);
+        client
+            .write_all(b"PROXY TCP4 10.0.0.1")
+            .await
+            .expect("could not write");
+        drop(client);
+        let result = Stream::read_header(server).await;
+        assert!(matches!(
+            result,
+            Err(Error::Parse(parser::Error::ShortHeader))
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+//! Receiving datagrams which start with a PROXY v2 header.
+//!
+//! Each datagram carries its own header, so there's no connection to read it off of once. Instead
+//! we strip it from every datagram as it comes in.
+use crate::network::NetworkSet;
+use crate::proxy::{Addr, Command, Error, parser};
+use std::net::SocketAddr;
+use tokio::io;
+use tokio::net;
+
+/// A [`tokio::net::UdpSocket`] behind a proxy which speaks PROXY v2 over UDP.
+///
+/// Anyone can send a datagram, so use [`UdpSocket::trusted`] to only believe the proxies.
+///
+/// Example:
+///
+/// ```rust
+/// use axum_proxied::proxy;
+///
+/// # async fn example() {
+/// let socket = proxy::UdpSocket::from(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap())
+///     .trusted("172.16.0.0/12".parse().unwrap());
+/// let mut buf = vec![0; 65535];
+/// loop {
+///     let Ok((len, addr, peer)) = socket.recv_from(&mut buf).await else {
+///         continue;
+///     };
+///     println!("{addr:?} (via {peer}) sent {:?}", &buf[..len]);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UdpSocket {
+    socket: net::UdpSocket,
+    trusted: Option<NetworkSet>,
+}
+
+impl UdpSocket {
+    pub fn new(socket: net::UdpSocket) -> Self {
+        Self {
+            socket,
+            trusted: None,
+        }
+    }
+
+    /// Only read headers from datagrams sent from these networks. Anyone else's are returned
+    /// as they are, with their own address.
+    pub fn trusted(mut self, networks: NetworkSet) -> Self {
+        self.trusted = Some(networks);
+        self
+    }
+
+    /// The underlying socket.
+    pub fn get_ref(&self) -> &net::UdpSocket {
+        &self.socket
+    }
+
+    /// The underlying socket.
+    pub fn into_inner(self) -> net::UdpSocket {
+        self.socket
+    }
+
+    /// Receive a datagram, and strip the PROXY header off of it.
+    ///
+    /// The payload is moved to the start of `buf`, and its length is returned along with the
+    /// proxied addresses and the peer which actually sent the datagram (i.e. the proxy, which is
+    /// where replies should go). Datagrams without a header are returned as-is, with the peer as
+    /// the source.
+    ///
+    /// As with [`tokio::net::UdpSocket::recv_from`], anything which doesn't fit in `buf` is
+    /// dropped, so leave room for the header. A malformed header only fails this one datagram, as
+    /// does a v1 header or one for a stream, since neither can come over UDP.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Addr, SocketAddr), Error> {
+        let (read, peer) = self.socket.recv_from(buf).await?;
+        if let Some(trusted) = &self.trusted
+            && !trusted.contains(peer.ip())
+        {
+            return Ok((read, Addr::from(peer), peer));
+        }
+        let parser::ParseResult(advance_by, header) = parser::parse_datagram(&buf[..read])?;
+        let addr = match header {
+            parser::Where::Underlying => Addr::from(peer),
+            parser::Where::Local => Addr::from(peer).with_command(Command::Local),
+            parser::Where::Header {
+                source,
+                destination,
+            } => Addr::new(source, destination).with_command(Command::Proxy),
+        };
+        buf.copy_within(advance_by..read, 0);
+        Ok((read - advance_by, addr, peer))
+    }
+
+    /// Send a datagram as-is, e.g. back to the proxy.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(buf, target).await
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl From<net::UdpSocket> for UdpSocket {
+    fn from(value: net::UdpSocket) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V2_UDP4: &[u8] = &[
+        0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A, // magic
+        0x21, 0x12, 0x00, 0x0C, // PROXY, INET + DGRAM, 12 bytes
+        10, 0, 0, 1, // source
+        10, 0, 0, 2, // destination
+        0x04, 0xD2, // 1234
+        0x00, 0x35, // 53
+    ];
+
+    async fn pair() -> (net::UdpSocket, UdpSocket) {
+        pair_with(|socket| socket).await
+    }
+
+    async fn pair_with(f: impl FnOnce(UdpSocket) -> UdpSocket) -> (net::UdpSocket, UdpSocket) {
+        let proxy = net::UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let socket = f(UdpSocket::from(
+            net::UdpSocket::bind("127.0.0.1:0").await.expect("bind"),
+        ));
+        proxy
+            .connect(socket.local_addr().expect("no local addr"))
+            .await
+            .expect("could not connect");
+        (proxy, socket)
+    }
+
+    #[tokio::test]
+    async fn strips_header() {
+        let (proxy, socket) = pair().await;
+        let mut datagram = V2_UDP4.to_vec();
+        datagram.extend_from_slice(b"payload");
+        proxy.send(&datagram).await.expect("could not send");
+
+        let mut buf = [0; 128];
+        let (len, addr, peer) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(&buf[..len], b"payload");
+        assert_eq!(
+            addr.source(),
+            "10.0.0.1:1234".parse::<SocketAddr>().expect("???")
+        );
+        assert_eq!(
+            addr.destination(),
+            "10.0.0.2:53".parse::<SocketAddr>().expect("???")
+        );
+        assert_eq!(addr.command(), Some(Command::Proxy));
+        assert_eq!(peer, proxy.local_addr().expect("no local addr"));
+    }
+
+    #[tokio::test]
+    async fn no_header() {
+        let (proxy, socket) = pair().await;
+        proxy.send(b"payload").await.expect("could not send");
+
+        let mut buf = [0; 128];
+        let (len, addr, peer) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(&buf[..len], b"payload");
+        assert_eq!(addr.source(), peer);
+        assert_eq!(addr.command(), None);
+    }
+
+    #[tokio::test]
+    async fn bad_header_only_fails_one_datagram() {
+        let (proxy, socket) = pair().await;
+        proxy
+            .send(&V2_UDP4[..V2_UDP4.len() - 1])
+            .await
+            .expect("could not send");
+        proxy.send(V2_UDP4).await.expect("could not send");
+
+        let mut buf = [0; 128];
+        assert!(matches!(
+            socket.recv_from(&mut buf).await,
+            Err(Error::Parse(parser::Error::ShortHeader))
+        ));
+        let (len, _, _) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(len, 0);
+    }
+
+    #[tokio::test]
+    async fn untrusted_header_is_ignored() {
+        let (proxy, socket) =
+            pair_with(|socket| socket.trusted("10.0.0.0/8".parse().expect("???"))).await;
+        proxy.send(V2_UDP4).await.expect("could not send");
+
+        let mut buf = [0; 128];
+        let (len, addr, peer) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(&buf[..len], V2_UDP4);
+        assert_eq!(addr.source(), peer);
+        assert_eq!(addr.command(), None);
+    }
+
+    #[tokio::test]
+    async fn trusted_header_is_stripped() {
+        let (proxy, socket) =
+            pair_with(|socket| socket.trusted("127.0.0.0/8".parse().expect("???"))).await;
+        proxy.send(V2_UDP4).await.expect("could not send");
+
+        let mut buf = [0; 128];
+        let (len, addr, _) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(len, 0);
+        assert_eq!(addr.command(), Some(Command::Proxy));
+    }
+
+    #[tokio::test]
+    async fn stream_headers_are_rejected() {
+        let (proxy, socket) = pair().await;
+        proxy
+            .send(b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 53\r\n")
+            .await
+            .expect("could not send");
+        let mut stream = V2_UDP4.to_vec();
+        stream[13] = 0x11;
+        proxy.send(&stream).await.expect("could not send");
+
+        let mut buf = [0; 128];
+        assert!(matches!(
+            socket.recv_from(&mut buf).await,
+            Err(Error::Parse(parser::Error::UnsupportedVersion(1)))
+        ));
+        assert!(matches!(
+            socket.recv_from(&mut buf).await,
+            Err(Error::Parse(parser::Error::UnsupportedProtocol(1)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn partial_magic_is_a_payload() {
+        let (proxy, socket) = pair().await;
+        proxy.send(b"PROX").await.expect("could not send");
+
+        let mut buf = [0; 128];
+        let (len, addr, peer) = socket.recv_from(&mut buf).await.expect("could not recv");
+        assert_eq!(&buf[..len], b"PROX");
+        assert_eq!(addr.source(), peer);
+    }
+}